@@ -0,0 +1 @@
+DROP INDEX crates_search_idx
\ No newline at end of file
@@ -0,0 +1,5 @@
+CREATE INDEX crates_search_idx ON crates USING GIN ((
+    setweight(to_tsvector('english', name), 'A') ||
+    setweight(to_tsvector('english', code), 'A') ||
+    setweight(to_tsvector('english', coalesce(description, '')), 'B')
+))
\ No newline at end of file
@@ -0,0 +1,6 @@
+DROP INDEX rustaceans_email_trgm_idx;
+DROP INDEX rustaceans_name_trgm_idx;
+DROP INDEX crates_code_trgm_idx;
+DROP INDEX crates_name_trgm_idx;
+
+DROP EXTENSION IF EXISTS pg_trgm;
\ No newline at end of file
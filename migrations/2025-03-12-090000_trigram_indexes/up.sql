@@ -0,0 +1,6 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX crates_name_trgm_idx ON crates USING GIN (name gin_trgm_ops);
+CREATE INDEX crates_code_trgm_idx ON crates USING GIN (code gin_trgm_ops);
+CREATE INDEX rustaceans_name_trgm_idx ON rustaceans USING GIN (name gin_trgm_ops);
+CREATE INDEX rustaceans_email_trgm_idx ON rustaceans USING GIN (email gin_trgm_ops);
\ No newline at end of file
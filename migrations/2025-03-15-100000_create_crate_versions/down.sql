@@ -0,0 +1 @@
+DROP TABLE crate_versions
\ No newline at end of file
@@ -0,0 +1,12 @@
+CREATE TABLE crate_versions (
+    id SERIAL PRIMARY KEY,
+    crate_id integer NOT NULL REFERENCES crates(id) ON DELETE CASCADE,
+    version varchar(64) NOT NULL,
+    description text,
+    yanked boolean NOT NULL DEFAULT false,
+    created_at TIMESTAMP DEFAULT NOW() NOT NULL,
+    UNIQUE (crate_id, version)
+);
+
+INSERT INTO crate_versions (crate_id, version, description, created_at)
+SELECT id, version, description, created_at FROM crates;
\ No newline at end of file
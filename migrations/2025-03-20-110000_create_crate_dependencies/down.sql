@@ -0,0 +1 @@
+DROP TABLE crate_dependencies
\ No newline at end of file
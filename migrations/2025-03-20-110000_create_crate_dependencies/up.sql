@@ -0,0 +1,12 @@
+CREATE TABLE crate_dependencies (
+    id SERIAL PRIMARY KEY,
+    crate_version_id integer NOT NULL REFERENCES crate_versions(id) ON DELETE CASCADE,
+    dependency_id integer NOT NULL REFERENCES crates(id) ON DELETE CASCADE,
+    req varchar(64) NOT NULL,
+    kind varchar(16) NOT NULL DEFAULT 'normal',
+    optional boolean NOT NULL DEFAULT false,
+    created_at TIMESTAMP DEFAULT NOW() NOT NULL,
+    UNIQUE (crate_version_id, dependency_id, kind)
+);
+
+CREATE INDEX crate_dependencies_dependency_id_idx ON crate_dependencies (dependency_id);
\ No newline at end of file
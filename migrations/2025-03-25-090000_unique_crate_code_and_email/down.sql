@@ -0,0 +1,2 @@
+DROP INDEX rustaceans_email_unique_idx;
+DROP INDEX crates_code_unique_idx;
@@ -0,0 +1,16 @@
+-- Codes differing only by case or by '-' versus '_' name the same crate.
+--
+-- Rows that already clash keep the value on the oldest row. The others get a
+-- '-duplicate-<id>' suffix on the code, or a '+duplicate-<id>' tag on the
+-- email, so the indexes can be built and owners can rename them.
+UPDATE crates SET code = left(code, 64 - length('-duplicate-' || id)) || '-duplicate-' || id
+WHERE id NOT IN (
+    SELECT min(id) FROM crates GROUP BY lower(replace(code, '-', '_'))
+);
+UPDATE rustaceans SET email = regexp_replace(email, '(@|$)', '+duplicate-' || id || '\1')
+WHERE id NOT IN (
+    SELECT min(id) FROM rustaceans GROUP BY lower(email)
+);
+
+CREATE UNIQUE INDEX crates_code_unique_idx ON crates (lower(replace(code, '-', '_')));
+CREATE UNIQUE INDEX rustaceans_email_unique_idx ON rustaceans (lower(email));
@@ -0,0 +1,5 @@
+DROP TRIGGER set_updated_at ON rustaceans;
+DROP TRIGGER set_updated_at ON crates;
+
+ALTER TABLE rustaceans DROP COLUMN updated_at;
+ALTER TABLE crates DROP COLUMN updated_at;
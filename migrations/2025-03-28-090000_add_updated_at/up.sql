@@ -0,0 +1,8 @@
+ALTER TABLE crates ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT NOW();
+ALTER TABLE rustaceans ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT NOW();
+
+UPDATE crates SET updated_at = created_at;
+UPDATE rustaceans SET updated_at = created_at;
+
+SELECT diesel_manage_updated_at('crates');
+SELECT diesel_manage_updated_at('rustaceans');
@@ -0,0 +1,29 @@
+-- Deleted rows may share a code or email with a live row or with each other.
+-- The live row, or else the oldest, keeps the value and the others are
+-- renamed as when the indexes were first built.
+UPDATE crates SET code = left(code, 64 - length('-duplicate-' || id)) || '-duplicate-' || id
+WHERE id IN (
+    SELECT id FROM (
+        SELECT id, row_number() OVER (
+            PARTITION BY lower(replace(code, '-', '_'))
+            ORDER BY deleted_at IS NOT NULL, id
+        ) AS n FROM crates
+    ) ranked WHERE n > 1
+);
+UPDATE rustaceans SET email = regexp_replace(email, '(@|$)', '+duplicate-' || id || '\1')
+WHERE id IN (
+    SELECT id FROM (
+        SELECT id, row_number() OVER (
+            PARTITION BY lower(email)
+            ORDER BY deleted_at IS NOT NULL, id
+        ) AS n FROM rustaceans
+    ) ranked WHERE n > 1
+);
+
+DROP INDEX rustaceans_email_unique_idx;
+CREATE UNIQUE INDEX rustaceans_email_unique_idx ON rustaceans (lower(email));
+DROP INDEX crates_code_unique_idx;
+CREATE UNIQUE INDEX crates_code_unique_idx ON crates (lower(replace(code, '-', '_')));
+
+ALTER TABLE rustaceans DROP COLUMN deleted_at;
+ALTER TABLE crates DROP COLUMN deleted_at;
@@ -0,0 +1,10 @@
+ALTER TABLE crates ADD COLUMN deleted_at TIMESTAMP;
+ALTER TABLE rustaceans ADD COLUMN deleted_at TIMESTAMP;
+
+-- Deleted rows give up their code or email until they are restored.
+DROP INDEX crates_code_unique_idx;
+CREATE UNIQUE INDEX crates_code_unique_idx ON crates (lower(replace(code, '-', '_')))
+    WHERE deleted_at IS NULL;
+DROP INDEX rustaceans_email_unique_idx;
+CREATE UNIQUE INDEX rustaceans_email_unique_idx ON rustaceans (lower(email))
+    WHERE deleted_at IS NULL;
@@ -0,0 +1,4 @@
+DROP TRIGGER record_history ON rustaceans;
+DROP TRIGGER record_history ON crates;
+DROP FUNCTION record_history();
+DROP TABLE history;
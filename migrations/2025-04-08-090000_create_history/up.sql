@@ -0,0 +1,58 @@
+CREATE TABLE history (
+    id SERIAL PRIMARY KEY,
+    record_table VARCHAR(64) NOT NULL,
+    record_id integer NOT NULL,
+    action VARCHAR(16) NOT NULL,
+    user_id integer REFERENCES users(id) ON DELETE SET NULL,
+    before JSONB,
+    after JSONB,
+    created_at TIMESTAMP DEFAULT NOW() NOT NULL
+);
+
+CREATE INDEX history_record_idx ON history (record_table, record_id, created_at);
+
+-- Rows that predate the history start with a snapshot of how they are now,
+-- dated by their last change, so point-in-time reads can find them.
+INSERT INTO history (record_table, record_id, action, after, created_at)
+SELECT 'crates', id, 'create', to_jsonb(crates), updated_at FROM crates;
+INSERT INTO history (record_table, record_id, action, after, created_at)
+SELECT 'rustaceans', id, 'create', to_jsonb(rustaceans), updated_at FROM rustaceans;
+
+-- Records every change to a row along with the user the application names for
+-- the transaction through the `cr8s.user_id` setting. Soft deletes and restores
+-- are updates of `deleted_at`; only a purge removes the row.
+CREATE FUNCTION record_history() RETURNS trigger AS $$
+DECLARE
+    action VARCHAR(16);
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        action := 'create';
+    ELSIF TG_OP = 'DELETE' THEN
+        action := 'purge';
+    ELSIF OLD IS NOT DISTINCT FROM NEW THEN
+        RETURN NULL;
+    ELSIF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+        action := 'delete';
+    ELSIF OLD.deleted_at IS NOT NULL AND NEW.deleted_at IS NULL THEN
+        action := 'restore';
+    ELSE
+        action := 'update';
+    END IF;
+
+    INSERT INTO history (record_table, record_id, action, user_id, before, after)
+    VALUES (
+        TG_TABLE_NAME,
+        CASE WHEN TG_OP = 'DELETE' THEN OLD.id ELSE NEW.id END,
+        action,
+        nullif(current_setting('cr8s.user_id', true), '')::integer,
+        CASE WHEN TG_OP = 'INSERT' THEN NULL ELSE to_jsonb(OLD) END,
+        CASE WHEN TG_OP = 'DELETE' THEN NULL ELSE to_jsonb(NEW) END
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER record_history AFTER INSERT OR UPDATE OR DELETE ON crates
+    FOR EACH ROW EXECUTE PROCEDURE record_history();
+CREATE TRIGGER record_history AFTER INSERT OR UPDATE OR DELETE ON rustaceans
+    FOR EACH ROW EXECUTE PROCEDURE record_history();
@@ -0,0 +1,13 @@
+-- Scopes are kept space separated, e.g. 'crates:read crates:write'.
+CREATE TABLE api_tokens(
+    id SERIAL PRIMARY KEY,
+    user_id integer NOT NULL REFERENCES users(id),
+    name varchar(64) NOT NULL,
+    token_hash varchar(64) NOT NULL UNIQUE,
+    scopes varchar(255) NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    last_used_at TIMESTAMP,
+    created_at TIMESTAMP DEFAULT NOW() NOT NULL
+);
+
+CREATE INDEX api_tokens_user_id_idx ON api_tokens (user_id);
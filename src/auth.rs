@@ -3,8 +3,13 @@ use argon2::password_hash::{rand_core::OsRng, SaltString};
 use argon2::PasswordHasher;
 use argon2::{password_hash::Error, Argon2};
 use argon2::{PasswordHash, PasswordVerifier};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
 use rand::distributions::Alphanumeric;
 use rand::Rng;
+use sha2::Sha256;
+use std::sync::OnceLock;
+use subtle::ConstantTimeEq;
 
 #[derive(serde::Deserialize)]
 pub struct Credentials {
@@ -12,17 +17,90 @@ pub struct Credentials {
     pub password: String,
 }
 
+#[derive(serde::Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+// In seconds. A session lives until it is idle for `idle`, and never longer
+// than `absolute` after login, refreshes included.
+pub struct SessionTimeouts {
+    pub idle: u32,
+    pub absolute: u32,
+}
+
+pub fn session_timeouts() -> &'static SessionTimeouts {
+    static TIMEOUTS: OnceLock<SessionTimeouts> = OnceLock::new();
+    TIMEOUTS.get_or_init(|| {
+        let seconds = |name: &str, default: u32| match std::env::var(name) {
+            Ok(value) => value
+                .parse()
+                .ok()
+                .filter(|seconds| *seconds > 0)
+                .unwrap_or_else(|| panic!("{} must be a positive number of seconds", name)),
+            Err(_) => default,
+        };
+        SessionTimeouts {
+            idle: seconds("SESSION_IDLE_TIMEOUT", 3 * 60 * 60 /*3h*/),
+            absolute: seconds("SESSION_ABSOLUTE_TIMEOUT", 24 * 60 * 60 /*24h*/),
+        }
+    })
+}
+
+pub fn session_secret() -> &'static [u8] {
+    static SECRET: OnceLock<Vec<u8>> = OnceLock::new();
+    SECRET.get_or_init(|| {
+        std::env::var("SESSION_SECRET")
+            .ok()
+            .filter(|secret| !secret.is_empty())
+            .expect("Cannot load session secret from env")
+            .into_bytes()
+    })
+}
+
+// Tokens are only stored as a keyed hash, so reading redis is not enough to
+// impersonate anyone.
+pub fn hash_token(token: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(session_secret()).expect("HMAC takes keys of any size");
+    mac.update(token.as_bytes());
+    URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+}
+
+// HMAC-SHA256 digests encode to 43 characters, raw session tokens are 128.
+pub fn is_token_hash(value: &str) -> bool {
+    value.len() == 43
+}
+
+pub fn tokens_match(a: &str, b: &str) -> bool {
+    a.as_bytes().ct_eq(b.as_bytes()).into()
+}
+
+// Tells personal API tokens apart from session tokens, which are alphanumeric
+pub const API_TOKEN_PREFIX: &str = "cr8s_";
+
+fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+pub fn generate_token() -> String {
+    random_string(128)
+}
+
+pub fn generate_api_token() -> String {
+    format!("{}{}", API_TOKEN_PREFIX, random_string(40))
+}
+
 pub fn authorize_user(user: &User, credentials: Credentials) -> Result<String, Error> {
     let argon2 = Argon2::default();
     let db_hash = PasswordHash::new(&user.password)?;
     argon2.verify_password(credentials.password.as_bytes(), &db_hash)?;
 
-    let session_id = rand::thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(128)
-        .map(char::from)
-        .collect();
-    Ok(session_id)
+    Ok(generate_token())
 }
 
 pub fn hash_password(password: String) -> Result<String, Error> {
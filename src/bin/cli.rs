@@ -36,6 +36,47 @@ async fn main() {
                         ),
                 ),
         )
+        .subcommand(
+            Command::new("crates")
+                .about("Crate management")
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("graph")
+                        .about("Print the crate dependency graph")
+                        .arg(
+                            Arg::new("format")
+                                .long("format")
+                                .value_parser(["dot", "json"])
+                                .default_value("dot"),
+                        )
+                        .arg(
+                            Arg::new("root")
+                                .long("root")
+                                .value_parser(value_parser!(i32)),
+                        )
+                        .arg(
+                            Arg::new("depth")
+                                .long("depth")
+                                .value_parser(value_parser!(u32)),
+                        )
+                        .arg(
+                            Arg::new("rustacean")
+                                .long("rustacean")
+                                .value_parser(value_parser!(i32)),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("purge")
+                .about("Permanently remove deleted crates and rustaceans")
+                .arg(
+                    Arg::new("older_than")
+                        .long("older-than")
+                        .help("Days since deletion")
+                        .required(true)
+                        .value_parser(value_parser!(i32).range(0..)),
+                ),
+        )
         .subcommand(
             Command::new("digest-send")
                 .about("Send a digest with latest crates via email")
@@ -77,6 +118,20 @@ async fn main() {
             }
             _ => {}
         },
+        Some(("crates", sub_matches)) => {
+            if let Some(("graph", sub_matches)) = sub_matches.subcommand() {
+                cr8s::commands::crates_graph(
+                    sub_matches.get_one::<String>("format").unwrap().to_owned(),
+                    sub_matches.get_one::<i32>("root").copied(),
+                    sub_matches.get_one::<u32>("depth").copied(),
+                    sub_matches.get_one::<i32>("rustacean").copied(),
+                )
+                .await
+            }
+        }
+        Some(("purge", args)) => {
+            cr8s::commands::purge(args.get_one::<i32>("older_than").unwrap().to_owned()).await
+        }
         Some(("digest-send", args)) => {
             cr8s::commands::digest_send(
                 args.get_one::<String>("email").unwrap().to_owned(),
@@ -4,26 +4,71 @@ use rocket_db_pools::Database;
 
 #[rocket::main]
 async fn main() {
+    // Fail at startup rather than on the first login
+    cr8s::auth::session_secret();
+    cr8s::auth::session_timeouts();
+
     let _ = rocket::build()
         .mount(
             "/",
             rocket::routes![
                 cr8s::rocket_routes::options,
                 cr8s::rocket_routes::authorization::login,
+                cr8s::rocket_routes::authorization::refresh_token,
                 cr8s::rocket_routes::authorization::me,
+                cr8s::rocket_routes::authorization::get_my_sessions,
+                cr8s::rocket_routes::authorization::delete_my_session,
+                cr8s::rocket_routes::authorization::get_my_tokens,
+                cr8s::rocket_routes::authorization::create_my_token,
+                cr8s::rocket_routes::authorization::delete_my_token,
+                cr8s::rocket_routes::authorization::logout,
+                cr8s::rocket_routes::authorization::logout_all,
+                cr8s::rocket_routes::authorization::get_user_sessions,
+                cr8s::rocket_routes::authorization::delete_user_sessions,
                 cr8s::rocket_routes::rustaceans::get_rustaceans,
+                cr8s::rocket_routes::rustaceans::autocomplete_rustaceans,
                 cr8s::rocket_routes::rustaceans::get_rustacean,
                 cr8s::rocket_routes::rustaceans::crate_rustacean,
                 cr8s::rocket_routes::rustaceans::update_rustacean,
+                cr8s::rocket_routes::rustaceans::patch_rustacean,
                 cr8s::rocket_routes::rustaceans::delete_rustaceans,
+                cr8s::rocket_routes::rustaceans::restore_rustacean,
                 cr8s::rocket_routes::crates::get_crates,
+                cr8s::rocket_routes::crates::search_crates,
+                cr8s::rocket_routes::crates::autocomplete_crates,
+                cr8s::rocket_routes::crates::get_crates_graph,
                 cr8s::rocket_routes::crates::get_crate,
+                cr8s::rocket_routes::crates::get_crate_by_code,
                 cr8s::rocket_routes::crates::crate_crate,
                 cr8s::rocket_routes::crates::update_crate,
+                cr8s::rocket_routes::crates::patch_crate,
+                cr8s::rocket_routes::crates::update_crate_by_code,
                 cr8s::rocket_routes::crates::delete_crates,
+                cr8s::rocket_routes::crates::delete_crate_by_code,
+                cr8s::rocket_routes::crates::restore_crate,
+                cr8s::rocket_routes::crates::get_crate_history,
+                cr8s::rocket_routes::crates::get_crate_versions,
+                cr8s::rocket_routes::crates::create_crate_version,
+                cr8s::rocket_routes::crates::get_crate_dependencies,
+                cr8s::rocket_routes::crates::create_crate_dependency,
+                cr8s::rocket_routes::crates::get_crate_dependents,
+                cr8s::rocket_routes::crates::resolve_crate_version,
+            ],
+        )
+        .register(
+            "/",
+            rocket::catchers![
+                cr8s::rocket_routes::bad_request,
+                cr8s::rocket_routes::unauthorized,
+                cr8s::rocket_routes::forbidden,
+                cr8s::rocket_routes::not_found,
+                cr8s::rocket_routes::unprocessable_entity,
+                cr8s::rocket_routes::service_unavailable,
+                cr8s::rocket_routes::internal_error,
             ],
         )
         .attach(cr8s::rocket_routes::Cors)
+        .attach(cr8s::rocket_routes::RequestId)
         .attach(cr8s::rocket_routes::DbConn::init())
         .attach(cr8s::rocket_routes::CacheConn::init())
         .launch()
@@ -2,16 +2,28 @@ use std::str::FromStr;
 
 use chrono::{Datelike, Utc};
 use diesel_async::{AsyncConnection, AsyncPgConnection};
+use serde::Serialize;
 
 use tera::{Context, Tera};
 
 use crate::{
     auth::hash_password,
+    graph::{CrateGraph, GraphFilter},
     mail::HtmlMailer,
-    models::{NewUser, RoleCode},
-    repositories::{CrateRepository, RoleRepository, UserRepository},
+    models::{Crate, NewUser, RoleCode},
+    repositories::{
+        CrateRepository, CrateVersionRepository, RoleRepository, RustaceanRepository,
+        UserRepository,
+    },
 };
 
+#[derive(Serialize)]
+struct DigestCrate<'a> {
+    #[serde(flatten)]
+    krate: &'a Crate,
+    versions: Vec<&'a str>,
+}
+
 fn load_template_engine() -> Tera {
     Tera::new("templates/**/*.html").expect("Cannot load template engine")
 }
@@ -55,17 +67,69 @@ pub async fn delete_user(user_id: i32) {
     UserRepository::delete_user(&mut c, user_id).await.unwrap();
 }
 
+pub async fn crates_graph(
+    format: String,
+    root: Option<i32>,
+    depth: Option<u32>,
+    rustacean_id: Option<i32>,
+) {
+    let mut c = load_db_connection().await;
+    let filter = GraphFilter {
+        root,
+        depth,
+        rustacean_id,
+    };
+    let graph = CrateGraph::load(&mut c, &filter).await.unwrap();
+    match format.as_str() {
+        "json" => println!("{}", serde_json::to_string_pretty(&graph).unwrap()),
+        _ => print!("{}", graph.to_dot()),
+    }
+}
+
+pub async fn purge(older_than_days: i32) {
+    let mut c = load_db_connection().await;
+    let crates = CrateRepository::purge(&mut c, older_than_days)
+        .await
+        .unwrap();
+    let rustaceans = RustaceanRepository::purge(&mut c, older_than_days)
+        .await
+        .unwrap();
+    println!(
+        "Purged {} crates and {} rustaceans deleted more than {} days ago",
+        crates, rustaceans, older_than_days
+    );
+}
+
 pub async fn digest_send(email: String, hours_since: i32) {
     let mut c = load_db_connection().await;
     let tera = load_template_engine();
     let crates = CrateRepository::find_since(&mut c, hours_since)
         .await
         .unwrap();
-    if crates.len() > 0 {
+    if !crates.is_empty() {
+        let versions =
+            CrateVersionRepository::find_by_crates(&mut c, crates.iter().map(|c| c.id).collect())
+                .await
+                .unwrap();
+        let digest_crates: Vec<DigestCrate> = crates
+            .iter()
+            .map(|krate| DigestCrate {
+                krate,
+                versions: versions
+                    .iter()
+                    .filter(|v| v.crate_id == krate.id)
+                    .map(|v| v.version.as_str())
+                    .collect(),
+            })
+            .collect();
+
         let year = Utc::now().year();
+        let app_url =
+            std::env::var("APP_URL").unwrap_or_else(|_| "http://127.0.0.1:8000".to_owned());
         let mut context = Context::new();
-        context.insert("crates", &crates);
+        context.insert("crates", &digest_crates);
         context.insert("year", &year);
+        context.insert("app_url", app_url.trim_end_matches('/'));
 
         let smtp_host = std::env::var("SMTP_HOST").expect("Cannot load smtp host from env");
         let smtp_username =
@@ -0,0 +1,152 @@
+use std::collections::{BTreeSet, HashMap};
+
+use diesel::QueryResult;
+use diesel_async::AsyncPgConnection;
+use serde::Serialize;
+
+use crate::{
+    models::{CrateFilter, DependencyKind},
+    repositories::{CrateDependencyRepository, CrateRepository},
+};
+
+#[derive(Clone, Copy, Debug, Default, rocket::FromFormField)]
+pub enum GraphFormat {
+    #[default]
+    #[field(value = "json")]
+    Json,
+    #[field(value = "dot")]
+    Dot,
+}
+
+#[derive(Default)]
+pub struct GraphFilter {
+    pub root: Option<i32>,
+    pub depth: Option<u32>,
+    pub rustacean_id: Option<i32>,
+}
+
+#[derive(Serialize)]
+pub struct GraphNode {
+    pub id: i32,
+    pub rustacean_id: i32,
+    pub code: String,
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Serialize)]
+pub struct GraphEdge {
+    pub from: i32,
+    pub to: i32,
+    pub req: String,
+    pub kind: DependencyKind,
+    pub optional: bool,
+}
+
+#[derive(Serialize)]
+pub struct CrateGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl CrateGraph {
+    // Edges come from the latest version of each crate. Filtering by rustacean
+    // narrows the node set, and a root keeps only what it reaches within depth.
+    pub async fn load(c: &mut AsyncPgConnection, filter: &GraphFilter) -> QueryResult<Self> {
+        let crate_filter = CrateFilter {
+            rustacean_id: filter.rustacean_id,
+            ..Default::default()
+        };
+        let crates = CrateRepository::find_all(c, &crate_filter).await?;
+        let ids: BTreeSet<i32> = crates.iter().map(|c| c.id).collect();
+        let edges: Vec<GraphEdge> = CrateDependencyRepository::find_latest(c)
+            .await?
+            .into_iter()
+            .filter(|(d, from)| ids.contains(from) && ids.contains(&d.dependency_id))
+            .map(|(d, from)| GraphEdge {
+                from,
+                to: d.dependency_id,
+                req: d.req,
+                kind: d.kind,
+                optional: d.optional,
+            })
+            .collect();
+
+        let reachable = match filter.root {
+            Some(root) => {
+                if !ids.contains(&root) {
+                    return Err(diesel::result::Error::NotFound);
+                }
+                let mut adjacency: HashMap<i32, Vec<i32>> = HashMap::new();
+                for edge in &edges {
+                    adjacency.entry(edge.from).or_default().push(edge.to);
+                }
+                let mut reachable = BTreeSet::from([root]);
+                let mut frontier = vec![root];
+                let mut depth = 0;
+                while !frontier.is_empty() && filter.depth.is_none_or(|max| depth < max) {
+                    frontier = frontier
+                        .iter()
+                        .flat_map(|id| adjacency.get(id).into_iter().flatten())
+                        .filter(|id| reachable.insert(**id))
+                        .copied()
+                        .collect();
+                    depth += 1;
+                }
+                reachable
+            }
+            None => ids,
+        };
+
+        Ok(CrateGraph {
+            nodes: crates
+                .into_iter()
+                .filter(|c| reachable.contains(&c.id))
+                .map(|c| GraphNode {
+                    id: c.id,
+                    rustacean_id: c.rustacean_id,
+                    code: c.code,
+                    name: c.name,
+                    version: c.version,
+                })
+                .collect(),
+            edges: edges
+                .into_iter()
+                .filter(|e| reachable.contains(&e.from) && reachable.contains(&e.to))
+                .collect(),
+        })
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph crates {\n");
+        for node in &self.nodes {
+            dot.push_str(&format!(
+                "    c{} [label=\"{}\\n{}\"];\n",
+                node.id,
+                escape(&node.code),
+                escape(&node.version)
+            ));
+        }
+        for edge in &self.edges {
+            let style = match (edge.kind, edge.optional) {
+                (_, true) => ", style=dotted",
+                (DependencyKind::Dev, _) => ", style=dashed",
+                (DependencyKind::Build, _) => ", style=bold",
+                (DependencyKind::Normal, _) => "",
+            };
+            dot.push_str(&format!(
+                "    c{} -> c{} [label=\"{}\"{}];\n",
+                edge.from,
+                edge.to,
+                escape(&edge.req),
+                style
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
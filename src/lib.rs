@@ -1,7 +1,10 @@
 pub mod auth;
 pub mod commands;
+mod graph;
 pub mod mail;
 mod models;
 mod repositories;
+mod resolver;
 pub mod rocket_routes;
 mod schema;
+pub mod semver;
@@ -17,8 +17,8 @@ pub struct HtmlMailer {
 impl HtmlMailer {
     pub fn send(
         self,
-        subject: &String,
-        email_to: &String,
+        subject: &str,
+        email_to: &str,
         template_name: &str,
         template_context: Context,
     ) -> Result<Response, Box<dyn Error>> {
@@ -1,7 +1,8 @@
-use std::{io::Write, str::FromStr};
+use std::{fmt, io::Write, str::FromStr};
 
 use crate::schema::*;
-use chrono::NaiveDateTime;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use diesel::{
     deserialize::{FromSql, FromSqlRow},
     expression::AsExpression,
@@ -11,47 +12,345 @@ use diesel::{
     sql_types::Text,
 };
 use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
 
-#[derive(Queryable, AsChangeset, Serialize, Deserialize)]
+fn not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        return Err(ValidationError::new("blank").with_message("must not be blank".into()));
+    }
+    Ok(())
+}
+
+// Crate codes start with a letter and otherwise use letters, digits, `-` and `_`.
+fn crate_code(value: &str) -> Result<(), ValidationError> {
+    let mut chars = value.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(ValidationError::new("code").with_message(
+            "must start with a letter and contain only letters, digits, '-' and '_'".into(),
+        ));
+    }
+    Ok(())
+}
+
+fn in_future(value: &NaiveDateTime) -> Result<(), ValidationError> {
+    if *value <= Utc::now().naive_utc() {
+        return Err(ValidationError::new("future").with_message("must be in the future".into()));
+    }
+    Ok(())
+}
+
+fn semver(value: &str) -> Result<(), ValidationError> {
+    value
+        .parse::<crate::semver::Version>()
+        .map(|_| ())
+        .map_err(|e| ValidationError::new("semver").with_message(e.to_string().into()))
+}
+
+#[derive(Queryable, QueryableByName, AsChangeset, Serialize, Deserialize, Validate)]
+#[diesel(table_name=rustaceans)]
 pub struct Rustacean {
     #[serde(skip_deserializing)]
     pub id: i32,
+    #[validate(custom(function = "not_blank"))]
     pub name: String,
+    #[validate(email(message = "must be a valid email address"))]
     pub email: String,
     #[serde(skip_deserializing)]
     pub created_at: NaiveDateTime,
+    #[serde(skip_deserializing)]
+    pub updated_at: NaiveDateTime,
+    #[serde(skip)]
+    pub deleted_at: Option<NaiveDateTime>,
 }
-#[derive(Insertable, Deserialize)]
+#[derive(Insertable, Deserialize, Validate)]
 #[diesel(table_name=rustaceans)]
 pub struct NewRustacean {
+    #[validate(custom(function = "not_blank"))]
     pub name: String,
+    #[validate(email(message = "must be a valid email address"))]
     pub email: String,
 }
 
-#[derive(Queryable, AsChangeset, Serialize, Deserialize)]
+#[derive(Queryable, QueryableByName, AsChangeset, Serialize, Deserialize, Clone, Validate)]
+#[diesel(table_name=crates)]
 pub struct Crate {
     #[serde(skip_deserializing)]
     pub id: i32,
     pub rustacean_id: i32,
+    #[validate(
+        length(max = 64, message = "must be at most 64 characters"),
+        custom(function = "crate_code")
+    )]
     pub code: String,
+    #[validate(
+        length(max = 128, message = "must be at most 128 characters"),
+        custom(function = "not_blank")
+    )]
     pub name: String,
+    #[validate(
+        length(max = 64, message = "must be at most 64 characters"),
+        custom(function = "semver")
+    )]
     pub version: String,
     pub description: Option<String>,
     #[serde(skip_deserializing)]
     pub created_at: NaiveDateTime,
+    #[serde(skip_deserializing)]
+    pub updated_at: NaiveDateTime,
+    #[serde(skip)]
+    pub deleted_at: Option<NaiveDateTime>,
 }
 
-#[derive(Insertable, Deserialize)]
+#[derive(Insertable, Deserialize, Validate)]
 #[diesel(table_name=crates)]
 pub struct NewCrate {
     pub rustacean_id: i32,
+    #[validate(
+        length(max = 64, message = "must be at most 64 characters"),
+        custom(function = "crate_code")
+    )]
     pub code: String,
+    #[validate(
+        length(max = 128, message = "must be at most 128 characters"),
+        custom(function = "not_blank")
+    )]
     pub name: String,
+    #[validate(
+        length(max = 64, message = "must be at most 64 characters"),
+        custom(function = "semver")
+    )]
     pub version: String,
     pub description: Option<String>,
 }
 
-#[derive(Queryable, AsChangeset, Serialize, Deserialize, Debug, Identifiable)]
+#[derive(Queryable, Serialize, Clone)]
+pub struct CrateVersion {
+    pub id: i32,
+    pub crate_id: i32,
+    pub version: String,
+    pub description: Option<String>,
+    pub yanked: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Deserialize, Validate)]
+#[diesel(table_name=crate_versions)]
+pub struct NewCrateVersion {
+    #[serde(skip_deserializing)]
+    pub crate_id: i32,
+    #[validate(
+        length(max = 64, message = "must be at most 64 characters"),
+        custom(function = "semver")
+    )]
+    pub version: String,
+    pub description: Option<String>,
+}
+
+#[derive(Queryable, Serialize)]
+pub struct CrateDependency {
+    pub id: i32,
+    pub crate_version_id: i32,
+    pub dependency_id: i32,
+    pub req: String,
+    pub kind: DependencyKind,
+    pub optional: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Deserialize)]
+#[diesel(table_name=crate_dependencies)]
+pub struct NewCrateDependency {
+    #[serde(skip_deserializing)]
+    pub crate_version_id: i32,
+    pub dependency_id: i32,
+    pub req: String,
+    #[serde(default)]
+    pub kind: DependencyKind,
+    #[serde(default)]
+    pub optional: bool,
+}
+
+#[derive(QueryableByName, Serialize)]
+pub struct CrateSearchHit {
+    #[diesel(embed)]
+    #[serde(flatten)]
+    pub krate: Crate,
+    #[diesel(sql_type = diesel::sql_types::Float4)]
+    pub rank: f32,
+    #[diesel(sql_type = Text)]
+    pub snippet: String,
+}
+
+#[derive(QueryableByName, Serialize)]
+pub struct CrateMatch {
+    #[diesel(embed)]
+    #[serde(flatten)]
+    pub krate: Crate,
+    #[diesel(sql_type = diesel::sql_types::Float4)]
+    pub score: f32,
+}
+
+#[derive(QueryableByName, Serialize)]
+pub struct RustaceanMatch {
+    #[diesel(embed)]
+    #[serde(flatten)]
+    pub rustacean: Rustacean,
+    #[diesel(sql_type = diesel::sql_types::Float4)]
+    pub score: f32,
+}
+
+#[derive(Default)]
+pub struct CrateFilter {
+    pub rustacean_id: Option<i32>,
+    pub created_after: Option<NaiveDateTime>,
+}
+
+#[derive(Default)]
+pub struct RustaceanFilter {
+    pub created_after: Option<NaiveDateTime>,
+}
+
+#[derive(Clone, Copy, Debug, Default, rocket::FromFormField)]
+pub enum SortField {
+    #[default]
+    #[field(value = "created_at")]
+    CreatedAt,
+    #[field(value = "name")]
+    Name,
+}
+
+#[derive(Clone, Copy, Debug, Default, rocket::FromFormField)]
+pub enum SortOrder {
+    #[field(value = "asc")]
+    Asc,
+    #[default]
+    #[field(value = "desc")]
+    Desc,
+}
+
+impl SortField {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortField::CreatedAt => "created_at",
+            SortField::Name => "name",
+        }
+    }
+}
+
+impl SortOrder {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortOrder::Asc => "asc",
+            SortOrder::Desc => "desc",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sort {
+    pub field: SortField,
+    pub order: SortOrder,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PageRequest {
+    pub page: i64,
+    pub per_page: i64,
+}
+
+impl PageRequest {
+    pub const DEFAULT_PER_PAGE: i64 = 20;
+    pub const MAX_PER_PAGE: i64 = 100;
+    // Keeps `offset` and `page * per_page` within an i64.
+    pub const MAX_PAGE: i64 = i64::MAX / Self::MAX_PER_PAGE;
+
+    pub fn new(page: Option<i64>, per_page: Option<i64>) -> Self {
+        PageRequest {
+            page: page.unwrap_or(1).clamp(1, Self::MAX_PAGE),
+            per_page: per_page
+                .unwrap_or(Self::DEFAULT_PER_PAGE)
+                .clamp(1, Self::MAX_PER_PAGE),
+        }
+    }
+
+    pub fn offset(&self) -> i64 {
+        (self.page - 1) * self.per_page
+    }
+}
+
+impl Default for PageRequest {
+    fn default() -> Self {
+        PageRequest::new(None, None)
+    }
+}
+
+#[derive(Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+impl<T> Page<T> {
+    pub fn has_next(&self) -> bool {
+        self.page * self.per_page < self.total
+    }
+
+    pub fn has_prev(&self) -> bool {
+        self.page > 1
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cursor {
+    pub created_at: NaiveDateTime,
+    pub id: i32,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        let raw = format!(
+            "{}:{}",
+            self.created_at.and_utc().timestamp_micros(),
+            self.id
+        );
+        URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    pub fn decode(token: &str) -> Option<Self> {
+        let raw = String::from_utf8(URL_SAFE_NO_PAD.decode(token).ok()?).ok()?;
+        let (micros, id) = raw.split_once(':')?;
+        Some(Cursor {
+            created_at: DateTime::from_timestamp_micros(micros.parse().ok()?)?.naive_utc(),
+            id: id.parse().ok()?,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct CursorPage<T> {
+    pub items: Vec<T>,
+    pub per_page: i64,
+    pub next_cursor: Option<String>,
+}
+
+// A change to a crate or rustacean, with the row as JSON before and after it.
+#[derive(Queryable, Serialize)]
+pub struct HistoryEntry {
+    pub id: i32,
+    pub record_table: String,
+    pub record_id: i32,
+    pub action: String,
+    pub user_id: Option<i32>,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Queryable, AsChangeset, Serialize, Deserialize, Debug, Clone, Identifiable)]
 pub struct User {
     pub id: i32,
     pub username: String,
@@ -98,6 +397,97 @@ pub struct NewUserRole {
     pub user_id: i32,
     pub role_id: i32,
 }
+
+// A login session as kept in redis. `id` is public, the token never leaves login.
+#[derive(Serialize)]
+pub struct Session {
+    pub id: String,
+    pub user_id: i32,
+    pub created_at: NaiveDateTime,
+    pub last_seen_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+pub struct NewSession {
+    pub user_id: i32,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+// What a personal API token may be used for. Scopes only narrow what the
+// roles of its user allow.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum ApiScope {
+    #[serde(rename = "crates:read")]
+    CratesRead,
+    #[serde(rename = "crates:write")]
+    CratesWrite,
+    #[serde(rename = "rustaceans:read")]
+    RustaceansRead,
+    #[serde(rename = "rustaceans:write")]
+    RustaceansWrite,
+}
+
+impl fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiScope::CratesRead => write!(f, "crates:read"),
+            ApiScope::CratesWrite => write!(f, "crates:write"),
+            ApiScope::RustaceansRead => write!(f, "rustaceans:read"),
+            ApiScope::RustaceansWrite => write!(f, "rustaceans:write"),
+        }
+    }
+}
+
+fn space_separated<S: serde::Serializer>(value: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(value.split_whitespace())
+}
+
+#[derive(Queryable, Selectable, Serialize)]
+#[diesel(table_name=api_tokens)]
+pub struct ApiToken {
+    pub id: i32,
+    #[serde(skip)]
+    pub user_id: i32,
+    pub name: String,
+    #[serde(serialize_with = "space_separated")]
+    pub scopes: String,
+    pub expires_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+impl ApiToken {
+    pub fn allows(&self, scope: ApiScope) -> bool {
+        let scope = scope.to_string();
+        self.scopes.split_whitespace().any(|s| s == scope)
+    }
+}
+
+#[derive(Deserialize, Validate)]
+pub struct ApiTokenRequest {
+    #[validate(
+        length(max = 64, message = "must be at most 64 characters"),
+        custom(function = "not_blank")
+    )]
+    pub name: String,
+    #[validate(length(min = 1, message = "must not be empty"))]
+    pub scopes: Vec<ApiScope>,
+    #[validate(custom(function = "in_future"))]
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=api_tokens)]
+pub struct NewApiToken {
+    pub user_id: i32,
+    pub name: String,
+    pub token_hash: String,
+    pub scopes: String,
+    pub expires_at: NaiveDateTime,
+}
 #[derive(AsExpression, Debug, FromSqlRow, Serialize, Deserialize)]
 #[diesel(sql_type=Text)]
 pub enum RoleCode {
@@ -106,12 +496,12 @@ pub enum RoleCode {
     Viewer,
 }
 
-impl ToString for RoleCode {
-    fn to_string(&self) -> String {
+impl fmt::Display for RoleCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            RoleCode::Admin => String::from("admin"),
-            RoleCode::Editor => String::from("editor"),
-            RoleCode::Viewer => String::from("viewer"),
+            RoleCode::Admin => write!(f, "admin"),
+            RoleCode::Editor => write!(f, "editor"),
+            RoleCode::Viewer => write!(f, "viewer"),
         }
     }
 }
@@ -153,3 +543,50 @@ impl ToSql<Text, Pg> for RoleCode {
         Ok(diesel::serialize::IsNull::No)
     }
 }
+
+#[derive(
+    AsExpression, Clone, Copy, Debug, Default, FromSqlRow, PartialEq, Serialize, Deserialize,
+)]
+#[diesel(sql_type=Text)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencyKind {
+    #[default]
+    Normal,
+    Dev,
+    Build,
+}
+
+impl fmt::Display for DependencyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DependencyKind::Normal => write!(f, "normal"),
+            DependencyKind::Dev => write!(f, "dev"),
+            DependencyKind::Build => write!(f, "build"),
+        }
+    }
+}
+
+impl FromSql<Text, Pg> for DependencyKind {
+    fn from_sql(value: PgValue<'_>) -> diesel::deserialize::Result<Self> {
+        match value.as_bytes() {
+            b"normal" => Ok(DependencyKind::Normal),
+            b"dev" => Ok(DependencyKind::Dev),
+            b"build" => Ok(DependencyKind::Build),
+            _ => Err("Unrecognized dependency kind".into()),
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for DependencyKind {
+    fn to_sql<'b>(
+        &'b self,
+        out: &mut diesel::serialize::Output<'b, '_, Pg>,
+    ) -> diesel::serialize::Result {
+        match self {
+            DependencyKind::Normal => out.write_all(b"normal")?,
+            DependencyKind::Dev => out.write_all(b"dev")?,
+            DependencyKind::Build => out.write_all(b"build")?,
+        };
+        Ok(diesel::serialize::IsNull::No)
+    }
+}
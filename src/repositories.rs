@@ -1,100 +1,877 @@
-use crate::{models::*, schema::*};
+use std::{cmp::Reverse, collections::HashMap};
 
+use crate::{
+    auth::{hash_token, is_token_hash, session_timeouts, tokens_match},
+    models::*,
+    schema::*,
+    semver,
+};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
 use diesel::{
     dsl::{now, IntervalDsl},
+    pg::Pg,
     prelude::*,
+    sql_types::{BigInt, Bool, Text},
+};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
 };
-use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use rand::{distributions::Alphanumeric, Rng};
 use rocket_db_pools::deadpool_redis::{
     self,
     redis::{AsyncCommands, RedisError},
 };
 
+sql_function!(fn lower(x: Text) -> Text);
+sql_function!(fn replace(x: Text, from: Text, to: Text) -> Text);
+sql_function!(fn set_config(name: Text, value: Text, is_local: Bool) -> Text);
+
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+// ts_headline copies crate names and descriptions verbatim, markup included,
+// so search frames matches with STX and ETX instead of tags. The snippet is
+// HTML-escaped before those become <mark> elements.
+fn highlight(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len());
+    for ch in snippet.chars() {
+        match ch {
+            '\u{2}' => html.push_str("<mark>"),
+            '\u{3}' => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            ch => html.push(ch),
+        }
+    }
+    html
+}
+
+// History rows the triggers write for the rest of the transaction are blamed
+// on `user`.
+async fn act_as(c: &mut AsyncPgConnection, user: &User) -> QueryResult<()> {
+    diesel::select(set_config("cr8s.user_id", user.id.to_string(), true))
+        .execute(c)
+        .await
+        .map(|_| ())
+}
+
+// Offset and keyset listings read the same `created_at`, `name` and `id`
+// columns of every table, so they are written once for each repository's
+// table, model and filter. `Self::filtered` applies the filter.
+macro_rules! paginated {
+    ($table:ident, $model:ty, $filter:ty) => {
+        // The newest change to any row, deleted or filtered out ones included:
+        // soft deletes and edits that move a row out of a filter bump
+        // `updated_at` too, and purges only remove rows no listing shows.
+        pub async fn last_modified(
+            c: &mut AsyncPgConnection,
+        ) -> QueryResult<Option<NaiveDateTime>> {
+            $table::table
+                .select(diesel::dsl::max($table::updated_at))
+                .get_result(c)
+                .await
+        }
+
+        pub async fn find_page(
+            c: &mut AsyncPgConnection,
+            filter: &$filter,
+            sort: Sort,
+            page: PageRequest,
+        ) -> QueryResult<Page<$model>> {
+            let total = Self::filtered(filter).count().get_result(c).await?;
+            let query = Self::filtered(filter);
+            let query = match (sort.field, sort.order) {
+                (SortField::CreatedAt, SortOrder::Asc) => {
+                    query.order(($table::created_at.asc(), $table::id.asc()))
+                }
+                (SortField::CreatedAt, SortOrder::Desc) => {
+                    query.order(($table::created_at.desc(), $table::id.desc()))
+                }
+                (SortField::Name, SortOrder::Asc) => {
+                    query.order(($table::name.asc(), $table::id.asc()))
+                }
+                (SortField::Name, SortOrder::Desc) => {
+                    query.order(($table::name.desc(), $table::id.desc()))
+                }
+            };
+            let items = query
+                .limit(page.per_page)
+                .offset(page.offset())
+                .load(c)
+                .await?;
+
+            Ok(Page {
+                items,
+                total,
+                page: page.page,
+                per_page: page.per_page,
+            })
+        }
+
+        pub async fn find_after(
+            c: &mut AsyncPgConnection,
+            filter: &$filter,
+            order: SortOrder,
+            cursor: Option<Cursor>,
+            per_page: i64,
+        ) -> QueryResult<CursorPage<$model>> {
+            let mut query = Self::filtered(filter);
+            query = match (order, cursor) {
+                (SortOrder::Asc, Some(cursor)) => query.filter(
+                    $table::created_at
+                        .gt(cursor.created_at)
+                        .or($table::created_at
+                            .eq(cursor.created_at)
+                            .and($table::id.gt(cursor.id))),
+                ),
+                (SortOrder::Desc, Some(cursor)) => query.filter(
+                    $table::created_at
+                        .lt(cursor.created_at)
+                        .or($table::created_at
+                            .eq(cursor.created_at)
+                            .and($table::id.lt(cursor.id))),
+                ),
+                (_, None) => query,
+            };
+            query = match order {
+                SortOrder::Asc => query.order(($table::created_at.asc(), $table::id.asc())),
+                SortOrder::Desc => query.order(($table::created_at.desc(), $table::id.desc())),
+            };
+            let mut items: Vec<$model> = query.limit(per_page + 1).load(c).await?;
+
+            let next_cursor = if items.len() as i64 > per_page {
+                items.truncate(per_page as usize);
+                items.last().map(|r| {
+                    Cursor {
+                        created_at: r.created_at,
+                        id: r.id,
+                    }
+                    .encode()
+                })
+            } else {
+                None
+            };
+
+            Ok(CursorPage {
+                items,
+                per_page,
+                next_cursor,
+            })
+        }
+    };
+}
+
 pub struct RustaceanRepository;
 
+// Deleted rustaceans and crates keep their rows, stamped with `deleted_at`,
+// until they are purged. Everything but `find_deleted` and `restore` skips them.
 impl RustaceanRepository {
+    paginated!(rustaceans, Rustacean, RustaceanFilter);
+
     pub async fn find(c: &mut AsyncPgConnection, id: i32) -> QueryResult<Rustacean> {
-        rustaceans::table.find(id).get_result(c).await
+        rustaceans::table
+            .find(id)
+            .filter(rustaceans::deleted_at.is_null())
+            .get_result(c)
+            .await
     }
 
-    pub async fn find_all(c: &mut AsyncPgConnection, limit: i64) -> QueryResult<Vec<Rustacean>> {
-        rustaceans::table.limit(limit).get_results(c).await
+    pub async fn find_deleted(c: &mut AsyncPgConnection, id: i32) -> QueryResult<Rustacean> {
+        rustaceans::table
+            .find(id)
+            .filter(rustaceans::deleted_at.is_not_null())
+            .get_result(c)
+            .await
+    }
+
+    // Emails are unique regardless of case.
+    pub async fn find_by_email(c: &mut AsyncPgConnection, email: &str) -> QueryResult<Rustacean> {
+        rustaceans::table
+            .filter(rustaceans::deleted_at.is_null())
+            .filter(lower(rustaceans::email).eq(lower(email)))
+            .get_result(c)
+            .await
+    }
+
+    pub async fn autocomplete(
+        c: &mut AsyncPgConnection,
+        prefix: &str,
+        limit: i64,
+    ) -> QueryResult<Vec<RustaceanMatch>> {
+        diesel::sql_query(
+            "SELECT rustaceans.*,
+                    greatest(word_similarity($1, name), word_similarity($1, email)) AS score
+             FROM rustaceans
+             WHERE deleted_at IS NULL
+               AND ($1 <% name OR $1 <% email OR name % $1 OR email % $1
+                    OR name ILIKE $2 OR email ILIKE $2)
+             ORDER BY score DESC, rustaceans.id
+             LIMIT $3",
+        )
+        .bind::<Text, _>(prefix)
+        .bind::<Text, _>(format!("{}%", escape_like(prefix)))
+        .bind::<BigInt, _>(limit)
+        .load(c)
+        .await
+    }
+
+    fn filtered(filter: &RustaceanFilter) -> rustaceans::BoxedQuery<'static, Pg> {
+        let mut query = rustaceans::table
+            .filter(rustaceans::deleted_at.is_null())
+            .into_boxed();
+        if let Some(created_after) = filter.created_after {
+            query = query.filter(rustaceans::created_at.gt(created_after));
+        }
+        query
     }
 
     pub async fn create(
         c: &mut AsyncPgConnection,
         new_rustacean: NewRustacean,
+        user: &User,
     ) -> QueryResult<Rustacean> {
-        diesel::insert_into(rustaceans::table)
-            .values(new_rustacean)
-            .get_result(c)
-            .await
+        c.transaction(|c| {
+            async move {
+                act_as(c, user).await?;
+                diesel::insert_into(rustaceans::table)
+                    .values(new_rustacean)
+                    .get_result(c)
+                    .await
+            }
+            .scope_boxed()
+        })
+        .await
     }
 
     pub async fn update(
         c: &mut AsyncPgConnection,
         id: i32,
         rustacean: Rustacean,
+        updated_at: Option<NaiveDateTime>,
+        user: &User,
     ) -> QueryResult<Rustacean> {
-        diesel::update(rustaceans::table.find(id))
-            .set((
-                rustaceans::name.eq(rustacean.name),
-                rustaceans::email.eq(rustacean.email),
-            ))
-            .get_result(c)
-            .await
+        c.transaction(|c| {
+            async move {
+                act_as(c, user).await?;
+                let target = rustaceans::table
+                    .find(id)
+                    .filter(rustaceans::deleted_at.is_null());
+                let changes = (
+                    rustaceans::name.eq(rustacean.name),
+                    rustaceans::email.eq(rustacean.email),
+                );
+                match updated_at {
+                    Some(updated_at) => {
+                        diesel::update(target.filter(rustaceans::updated_at.eq(updated_at)))
+                            .set(changes)
+                            .get_result(c)
+                            .await
+                    }
+                    None => diesel::update(target).set(changes).get_result(c).await,
+                }
+            }
+            .scope_boxed()
+        })
+        .await
     }
 
-    pub async fn delete(c: &mut AsyncPgConnection, id: i32) -> QueryResult<usize> {
-        diesel::delete(rustaceans::table.find(id)).execute(c).await
+    // With `cascade`, the rustacean's crates are deleted too, at the same
+    // instant so `restore` can tell them apart.
+    pub async fn delete(
+        c: &mut AsyncPgConnection,
+        id: i32,
+        cascade: bool,
+        updated_at: Option<NaiveDateTime>,
+        user: &User,
+    ) -> QueryResult<usize> {
+        c.transaction(|c| {
+            async move {
+                act_as(c, user).await?;
+                let target = rustaceans::table
+                    .find(id)
+                    .filter(rustaceans::deleted_at.is_null());
+                if let Some(updated_at) = updated_at {
+                    let current = target
+                        .filter(rustaceans::updated_at.eq(updated_at))
+                        .select(rustaceans::id)
+                        .for_update()
+                        .first::<i32>(c)
+                        .await
+                        .optional()?;
+                    if current.is_none() {
+                        return Ok(0);
+                    }
+                }
+                if cascade {
+                    diesel::update(
+                        crates::table
+                            .filter(crates::rustacean_id.eq(id))
+                            .filter(crates::deleted_at.is_null()),
+                    )
+                    .set(crates::deleted_at.eq(now.nullable()))
+                    .execute(c)
+                    .await?;
+                }
+                diesel::update(target)
+                    .set(rustaceans::deleted_at.eq(now.nullable()))
+                    .execute(c)
+                    .await
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    // Crates deleted along with the rustacean come back with it.
+    pub async fn restore(
+        c: &mut AsyncPgConnection,
+        id: i32,
+        user: &User,
+    ) -> QueryResult<Rustacean> {
+        c.transaction(|c| {
+            async move {
+                act_as(c, user).await?;
+                let rustacean = Self::find_deleted(c, id).await?;
+                diesel::update(
+                    crates::table
+                        .filter(crates::rustacean_id.eq(id))
+                        .filter(crates::deleted_at.eq(rustacean.deleted_at)),
+                )
+                .set(crates::deleted_at.eq(None::<NaiveDateTime>))
+                .execute(c)
+                .await?;
+                diesel::update(rustaceans::table.find(id))
+                    .set(rustaceans::deleted_at.eq(None::<NaiveDateTime>))
+                    .get_result(c)
+                    .await
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    // Removes rustaceans deleted more than `days` ago for good, with all their
+    // crates.
+    pub async fn purge(c: &mut AsyncPgConnection, days: i32) -> QueryResult<usize> {
+        c.transaction(|c| {
+            async move {
+                let purged = rustaceans::table
+                    .filter(rustaceans::deleted_at.lt((now - days.days()).nullable()))
+                    .select(rustaceans::id);
+                diesel::delete(crates::table.filter(crates::rustacean_id.eq_any(purged)))
+                    .execute(c)
+                    .await?;
+                diesel::delete(
+                    rustaceans::table
+                        .filter(rustaceans::deleted_at.lt((now - days.days()).nullable())),
+                )
+                .execute(c)
+                .await
+            }
+            .scope_boxed()
+        })
+        .await
     }
 }
 
 pub struct CrateRepository;
 
+const CRATE_SEARCH_DOCUMENT: &str = "(
+    setweight(to_tsvector('english', name), 'A') ||
+    setweight(to_tsvector('english', code), 'A') ||
+    setweight(to_tsvector('english', coalesce(description, '')), 'B')
+)";
+
+#[derive(QueryableByName)]
+struct Count {
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
+
 impl CrateRepository {
+    paginated!(crates, Crate, CrateFilter);
+
     pub async fn find(c: &mut AsyncPgConnection, id: i32) -> QueryResult<Crate> {
-        crates::table.find(id).get_result(c).await
+        crates::table
+            .find(id)
+            .filter(crates::deleted_at.is_null())
+            .get_result(c)
+            .await
     }
 
-    pub async fn find_all(c: &mut AsyncPgConnection, limit: i64) -> QueryResult<Vec<Crate>> {
-        crates::table.limit(limit).load(c).await
+    pub async fn find_deleted(c: &mut AsyncPgConnection, id: i32) -> QueryResult<Crate> {
+        crates::table
+            .find(id)
+            .filter(crates::deleted_at.is_not_null())
+            .get_result(c)
+            .await
     }
 
-    pub async fn create(c: &mut AsyncPgConnection, new_crate: NewCrate) -> QueryResult<Crate> {
-        diesel::insert_into(crates::table)
-            .values(new_crate)
+    // Codes are unique regardless of case and of `-` versus `_`.
+    pub async fn find_by_code(c: &mut AsyncPgConnection, code: &str) -> QueryResult<Crate> {
+        crates::table
+            .filter(crates::deleted_at.is_null())
+            .filter(lower(replace(crates::code, "-", "_")).eq(lower(replace(code, "-", "_"))))
             .get_result(c)
             .await
     }
 
-    pub async fn update(c: &mut AsyncPgConnection, id: i32, u_crate: Crate) -> QueryResult<Crate> {
-        diesel::update(crates::table.find(id))
-            .set((
-                crates::rustacean_id.eq(u_crate.rustacean_id),
-                crates::code.eq(u_crate.code),
-                crates::name.eq(u_crate.name),
-                crates::version.eq(u_crate.version),
-                crates::description.eq(u_crate.description),
-            ))
-            .get_result(c)
+    pub async fn find_all(
+        c: &mut AsyncPgConnection,
+        filter: &CrateFilter,
+    ) -> QueryResult<Vec<Crate>> {
+        Self::filtered(filter).order(crates::id.asc()).load(c).await
+    }
+
+    fn filtered(filter: &CrateFilter) -> crates::BoxedQuery<'static, Pg> {
+        let mut query = crates::table
+            .filter(crates::deleted_at.is_null())
+            .into_boxed();
+        if let Some(rustacean_id) = filter.rustacean_id {
+            query = query.filter(crates::rustacean_id.eq(rustacean_id));
+        }
+        if let Some(created_after) = filter.created_after {
+            query = query.filter(crates::created_at.gt(created_after));
+        }
+        query
+    }
+
+    pub async fn create(
+        c: &mut AsyncPgConnection,
+        new_crate: NewCrate,
+        user: &User,
+    ) -> QueryResult<Crate> {
+        c.transaction(|c| {
+            async move {
+                act_as(c, user).await?;
+                let krate: Crate = diesel::insert_into(crates::table)
+                    .values(new_crate)
+                    .get_result(c)
+                    .await?;
+                CrateVersionRepository::record(c, &krate).await?;
+                Ok(krate)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    // A version below the latest release is refused as a `CheckViolation`.
+    pub async fn update(
+        c: &mut AsyncPgConnection,
+        id: i32,
+        u_crate: Crate,
+        updated_at: Option<NaiveDateTime>,
+        user: &User,
+    ) -> QueryResult<Crate> {
+        c.transaction(|c| {
+            async move {
+                act_as(c, user).await?;
+                let target = crates::table.find(id).filter(crates::deleted_at.is_null());
+                target.for_update().get_result::<Crate>(c).await?;
+                let releases = CrateVersionRepository::find_by_crate(c, id).await?;
+                if let Some(latest) = releases.as_slice().first() {
+                    if semver::compare(&u_crate.version, &latest.version).is_lt() {
+                        return Err(diesel::result::Error::DatabaseError(
+                            diesel::result::DatabaseErrorKind::CheckViolation,
+                            Box::new(format!(
+                                "Version is lower than the latest release {}",
+                                latest.version
+                            )),
+                        ));
+                    }
+                }
+                let changes = (
+                    crates::rustacean_id.eq(u_crate.rustacean_id),
+                    crates::code.eq(u_crate.code),
+                    crates::name.eq(u_crate.name),
+                    crates::version.eq(u_crate.version),
+                    crates::description.eq(u_crate.description),
+                );
+                let krate: Crate = match updated_at {
+                    Some(updated_at) => {
+                        diesel::update(target.filter(crates::updated_at.eq(updated_at)))
+                            .set(changes)
+                            .get_result(c)
+                            .await?
+                    }
+                    None => diesel::update(target).set(changes).get_result(c).await?,
+                };
+                CrateVersionRepository::record(c, &krate).await?;
+                Ok(krate)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    // Crates with a dependency on any version of this crate.
+    pub async fn find_dependents(c: &mut AsyncPgConnection, id: i32) -> QueryResult<Vec<Crate>> {
+        let dependents = crate_versions::table
+            .inner_join(crate_dependencies::table)
+            .filter(crate_dependencies::dependency_id.eq(id))
+            .select(crate_versions::crate_id);
+        crates::table
+            .filter(crates::deleted_at.is_null())
+            .filter(crates::id.eq_any(dependents))
+            .order(crates::code.asc())
+            .load(c)
             .await
     }
 
-    pub async fn delete(c: &mut AsyncPgConnection, id: i32) -> QueryResult<usize> {
-        diesel::delete(crates::table.find(id)).execute(c).await
+    // Dependency records other crates hold on this one are kept, so a restore
+    // brings them back; they are hidden while the crate is deleted.
+    pub async fn delete(
+        c: &mut AsyncPgConnection,
+        id: i32,
+        updated_at: Option<NaiveDateTime>,
+        user: &User,
+    ) -> QueryResult<usize> {
+        c.transaction(|c| {
+            async move {
+                act_as(c, user).await?;
+                let target = crates::table.find(id).filter(crates::deleted_at.is_null());
+                if let Some(updated_at) = updated_at {
+                    let current = target
+                        .filter(crates::updated_at.eq(updated_at))
+                        .select(crates::id)
+                        .for_update()
+                        .first::<i32>(c)
+                        .await
+                        .optional()?;
+                    if current.is_none() {
+                        return Ok(0);
+                    }
+                }
+                diesel::update(target)
+                    .set(crates::deleted_at.eq(now.nullable()))
+                    .execute(c)
+                    .await
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    pub async fn restore(c: &mut AsyncPgConnection, id: i32, user: &User) -> QueryResult<Crate> {
+        c.transaction(|c| {
+            async move {
+                act_as(c, user).await?;
+                diesel::update(
+                    crates::table
+                        .find(id)
+                        .filter(crates::deleted_at.is_not_null()),
+                )
+                .set(crates::deleted_at.eq(None::<NaiveDateTime>))
+                .get_result(c)
+                .await
+            }
+            .scope_boxed()
+        })
+        .await
     }
+
+    // Removes crates deleted more than `days` ago for good. Their versions and
+    // the dependencies those declare cascade with them, as do dependency
+    // records other crates hold on them.
+    pub async fn purge(c: &mut AsyncPgConnection, days: i32) -> QueryResult<usize> {
+        diesel::delete(crates::table.filter(crates::deleted_at.lt((now - days.days()).nullable())))
+            .execute(c)
+            .await
+    }
+
+    pub async fn search(
+        c: &mut AsyncPgConnection,
+        q: &str,
+        page: PageRequest,
+    ) -> QueryResult<Page<CrateSearchHit>> {
+        let total = diesel::sql_query(format!(
+            "SELECT count(*) AS count FROM crates, websearch_to_tsquery('english', $1) query
+             WHERE deleted_at IS NULL AND {} @@ query",
+            CRATE_SEARCH_DOCUMENT
+        ))
+        .bind::<Text, _>(q)
+        .get_result::<Count>(c)
+        .await?
+        .count;
+
+        let items = diesel::sql_query(format!(
+            "SELECT crates.*,
+                    ts_rank({doc}, query) AS rank,
+                    ts_headline('english',
+                                translate(name || ' ' || coalesce(description, ''), chr(2) || chr(3), ''),
+                                query,
+                                'StartSel=' || chr(2) || ', StopSel=' || chr(3) || ', MaxFragments=2') AS snippet
+             FROM crates, websearch_to_tsquery('english', $1) query
+             WHERE deleted_at IS NULL AND {doc} @@ query
+             ORDER BY rank DESC, crates.id
+             LIMIT $2 OFFSET $3",
+            doc = CRATE_SEARCH_DOCUMENT
+        ))
+        .bind::<Text, _>(q)
+        .bind::<BigInt, _>(page.per_page)
+        .bind::<BigInt, _>(page.offset())
+        .load::<CrateSearchHit>(c)
+        .await?
+        .into_iter()
+        .map(|hit| CrateSearchHit {
+            snippet: highlight(&hit.snippet),
+            ..hit
+        })
+        .collect();
+
+        Ok(Page {
+            items,
+            total,
+            page: page.page,
+            per_page: page.per_page,
+        })
+    }
+
+    pub async fn autocomplete(
+        c: &mut AsyncPgConnection,
+        prefix: &str,
+        limit: i64,
+    ) -> QueryResult<Vec<CrateMatch>> {
+        diesel::sql_query(
+            "SELECT crates.*,
+                    greatest(word_similarity($1, name), word_similarity($1, code)) AS score
+             FROM crates
+             WHERE deleted_at IS NULL
+               AND ($1 <% name OR $1 <% code OR name % $1 OR code % $1
+                    OR name ILIKE $2 OR code ILIKE $2)
+             ORDER BY score DESC, crates.id
+             LIMIT $3",
+        )
+        .bind::<Text, _>(prefix)
+        .bind::<Text, _>(format!("{}%", escape_like(prefix)))
+        .bind::<BigInt, _>(limit)
+        .load(c)
+        .await
+    }
+
     pub async fn find_since(
         c: &mut AsyncPgConnection,
         hours_since: i32,
     ) -> QueryResult<Vec<Crate>> {
         crates::table
+            .filter(crates::deleted_at.is_null())
             .filter(crates::created_at.ge(now - hours_since.hours()))
             .load(c)
             .await
     }
 }
 
+pub struct CrateVersionRepository;
+
+impl CrateVersionRepository {
+    pub async fn find_by_crate(
+        c: &mut AsyncPgConnection,
+        crate_id: i32,
+    ) -> QueryResult<Vec<CrateVersion>> {
+        let mut versions: Vec<CrateVersion> = crate_versions::table
+            .filter(crate_versions::crate_id.eq(crate_id))
+            .order((crate_versions::created_at.desc(), crate_versions::id.desc()))
+            .load(c)
+            .await?;
+        versions.sort_by(|a, b| semver::compare(&b.version, &a.version));
+        Ok(versions)
+    }
+
+    pub async fn find_by_version(
+        c: &mut AsyncPgConnection,
+        crate_id: i32,
+        version: &str,
+    ) -> QueryResult<CrateVersion> {
+        crate_versions::table
+            .filter(crate_versions::crate_id.eq(crate_id))
+            .filter(crate_versions::version.eq(version))
+            .get_result(c)
+            .await
+    }
+
+    pub async fn find_by_crates(
+        c: &mut AsyncPgConnection,
+        crate_ids: Vec<i32>,
+    ) -> QueryResult<Vec<CrateVersion>> {
+        let mut versions: Vec<CrateVersion> = crate_versions::table
+            .filter(crate_versions::crate_id.eq_any(crate_ids))
+            .load(c)
+            .await?;
+        versions.sort_by(|a, b| {
+            a.crate_id
+                .cmp(&b.crate_id)
+                .then_with(|| semver::compare(&b.version, &a.version))
+        });
+        Ok(versions)
+    }
+
+    pub async fn create(
+        c: &mut AsyncPgConnection,
+        new_version: NewCrateVersion,
+        user: &User,
+    ) -> QueryResult<CrateVersion> {
+        c.transaction(|c| {
+            async move {
+                act_as(c, user).await?;
+                let krate: Crate = crates::table
+                    .find(new_version.crate_id)
+                    .filter(crates::deleted_at.is_null())
+                    .for_update()
+                    .get_result(c)
+                    .await?;
+                if semver::compare(&new_version.version, &krate.version).is_ge() {
+                    diesel::update(crates::table.find(krate.id))
+                        .set((
+                            crates::version.eq(&new_version.version),
+                            crates::description.eq(new_version
+                                .description
+                                .as_ref()
+                                .or(krate.description.as_ref())),
+                        ))
+                        .execute(c)
+                        .await?;
+                }
+                diesel::insert_into(crate_versions::table)
+                    .values(&new_version)
+                    .get_result(c)
+                    .await
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    async fn record(c: &mut AsyncPgConnection, krate: &Crate) -> QueryResult<usize> {
+        diesel::insert_into(crate_versions::table)
+            .values(NewCrateVersion {
+                crate_id: krate.id,
+                version: krate.version.clone(),
+                description: krate.description.clone(),
+            })
+            .on_conflict((crate_versions::crate_id, crate_versions::version))
+            .do_nothing()
+            .execute(c)
+            .await
+    }
+}
+
+pub struct CrateDependencyRepository;
+
+impl CrateDependencyRepository {
+    pub async fn find_by_version(
+        c: &mut AsyncPgConnection,
+        crate_version_id: i32,
+    ) -> QueryResult<Vec<(CrateDependency, Crate)>> {
+        crate_dependencies::table
+            .inner_join(crates::table)
+            .filter(crates::deleted_at.is_null())
+            .filter(crate_dependencies::crate_version_id.eq(crate_version_id))
+            .order(crates::code.asc())
+            .load(c)
+            .await
+    }
+
+    pub async fn find_by_versions(
+        c: &mut AsyncPgConnection,
+        crate_version_ids: Vec<i32>,
+    ) -> QueryResult<Vec<(CrateDependency, Crate)>> {
+        crate_dependencies::table
+            .inner_join(crates::table)
+            .filter(crate_dependencies::crate_version_id.eq_any(crate_version_ids))
+            .order((crates::code.asc(), crate_dependencies::id.asc()))
+            .load(c)
+            .await
+    }
+
+    pub async fn find_latest(
+        c: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<(CrateDependency, i32)>> {
+        crate_dependencies::table
+            .inner_join(crate_versions::table.inner_join(crates::table))
+            .filter(crate_versions::version.eq(crates::version))
+            .select((crate_dependencies::all_columns, crates::id))
+            .order(crate_dependencies::id.asc())
+            .load(c)
+            .await
+    }
+
+    pub async fn find_dependents(
+        c: &mut AsyncPgConnection,
+        crate_id: i32,
+        all_versions: bool,
+    ) -> QueryResult<Vec<(CrateDependency, CrateVersion, Crate)>> {
+        let mut query = crate_dependencies::table
+            .inner_join(crate_versions::table.inner_join(crates::table))
+            .filter(crate_dependencies::dependency_id.eq(crate_id))
+            .filter(crates::deleted_at.is_null())
+            .select((
+                crate_dependencies::all_columns,
+                crate_versions::all_columns,
+                crates::all_columns,
+            ))
+            .order((crates::code.asc(), crate_versions::id.desc()))
+            .into_boxed();
+        if !all_versions {
+            query = query.filter(crate_versions::version.eq(crates::version));
+        }
+        query.load(c).await
+    }
+
+    pub async fn create(
+        c: &mut AsyncPgConnection,
+        new_dependency: NewCrateDependency,
+    ) -> QueryResult<CrateDependency> {
+        diesel::insert_into(crate_dependencies::table)
+            .values(new_dependency)
+            .get_result(c)
+            .await
+    }
+}
+
+pub struct HistoryRepository;
+
+impl HistoryRepository {
+    pub async fn find_by_record(
+        c: &mut AsyncPgConnection,
+        record_table: &str,
+        record_id: i32,
+    ) -> QueryResult<Vec<(HistoryEntry, Option<User>)>> {
+        history::table
+            .left_join(users::table)
+            .filter(history::record_table.eq(record_table))
+            .filter(history::record_id.eq(record_id))
+            .order((history::created_at.desc(), history::id.desc()))
+            .load(c)
+            .await
+    }
+
+    // The latest entry at or before `as_of`, whose `after` is the record as it
+    // was then.
+    pub async fn find_as_of(
+        c: &mut AsyncPgConnection,
+        record_table: &str,
+        record_id: i32,
+        as_of: NaiveDateTime,
+    ) -> QueryResult<HistoryEntry> {
+        history::table
+            .filter(history::record_table.eq(record_table))
+            .filter(history::record_id.eq(record_id))
+            .filter(history::created_at.le(as_of))
+            .order((history::created_at.desc(), history::id.desc()))
+            .first(c)
+            .await
+    }
+}
+
 pub struct UserRepository;
 
 impl UserRepository {
@@ -167,6 +944,9 @@ impl UserRepository {
         diesel::delete(users_roles::table.filter(users_roles::user_id.eq(id)))
             .execute(c)
             .await?;
+        diesel::delete(api_tokens::table.filter(api_tokens::user_id.eq(id)))
+            .execute(c)
+            .await?;
         diesel::delete(users::table.find(id)).execute(c).await
     }
 }
@@ -188,7 +968,7 @@ impl RoleRepository {
         let user_roles = UserRole::belonging_to(&user)
             .get_results::<UserRole>(c)
             .await?;
-        let role_ids: Vec<i32> = user_roles.iter().map(|ur| ur.id).collect();
+        let role_ids: Vec<i32> = user_roles.iter().map(|ur| ur.role_id).collect();
         Self::find_by_ids(c, role_ids).await
     }
 
@@ -197,20 +977,367 @@ impl RoleRepository {
     }
 }
 
+pub struct ApiTokenRepository;
+
+impl ApiTokenRepository {
+    pub async fn find_by_user(
+        c: &mut AsyncPgConnection,
+        user: &User,
+    ) -> QueryResult<Vec<ApiToken>> {
+        api_tokens::table
+            .filter(api_tokens::user_id.eq(user.id))
+            .order(api_tokens::created_at.desc())
+            .select(ApiToken::as_select())
+            .load(c)
+            .await
+    }
+
+    pub async fn create(c: &mut AsyncPgConnection, token: NewApiToken) -> QueryResult<ApiToken> {
+        diesel::insert_into(api_tokens::table)
+            .values(token)
+            .returning(ApiToken::as_returning())
+            .get_result(c)
+            .await
+    }
+
+    // Finds an unexpired token by its hash and records that it was used
+    pub async fn use_token(c: &mut AsyncPgConnection, token_hash: &str) -> QueryResult<ApiToken> {
+        diesel::update(
+            api_tokens::table
+                .filter(api_tokens::token_hash.eq(token_hash))
+                .filter(api_tokens::expires_at.gt(now)),
+        )
+        .set(api_tokens::last_used_at.eq(now.nullable()))
+        .returning(ApiToken::as_returning())
+        .get_result(c)
+        .await
+    }
+
+    pub async fn delete(c: &mut AsyncPgConnection, id: i32, user: &User) -> QueryResult<usize> {
+        diesel::delete(
+            api_tokens::table
+                .find(id)
+                .filter(api_tokens::user_id.eq(user.id)),
+        )
+        .execute(c)
+        .await
+    }
+}
+
 pub struct SessionRepository;
 impl SessionRepository {
+    // Everything known about a session, kept until its absolute timeout
+    fn session_key(id: &str) -> String {
+        format!("sessions/{}", id)
+    }
+
+    // Points an access token at its session, expires once the session is idle.
+    // Token keys, like the session's token field, hold the hash of a token.
+    fn token_key(digest: &str) -> String {
+        format!("tokens/{}", digest)
+    }
+
+    // Points a refresh token at its session. Rotated tokens stay around, so
+    // reusing one can be told apart from an unknown token.
+    fn refresh_token_key(digest: &str) -> String {
+        format!("refresh_tokens/{}", digest)
+    }
+
+    fn used_refresh_token_key(digest: &str) -> String {
+        format!("used_refresh_tokens/{}", digest)
+    }
+
+    fn user_sessions_key(user_id: i32) -> String {
+        format!("users/{}/sessions", user_id)
+    }
+
+    fn timestamp(fields: &HashMap<String, String>, name: &str) -> Option<NaiveDateTime> {
+        let secs = fields.get(name)?.parse().ok()?;
+        DateTime::from_timestamp(secs, 0).map(|at| at.naive_utc())
+    }
+
+    // Sessions from before tokens were hashed hold the raw token and are
+    // treated as gone.
+    fn from_fields(fields: &HashMap<String, String>) -> Option<Session> {
+        if !fields
+            .get("token")
+            .is_some_and(|token| is_token_hash(token))
+        {
+            return None;
+        }
+        Some(Session {
+            id: fields.get("id")?.clone(),
+            user_id: fields.get("user_id")?.parse().ok()?,
+            created_at: Self::timestamp(fields, "created_at")?,
+            last_seen_at: Self::timestamp(fields, "last_seen_at")?,
+            expires_at: Self::timestamp(fields, "expires_at")?,
+            ip: fields.get("ip").cloned(),
+            user_agent: fields.get("user_agent").cloned(),
+        })
+    }
+
+    async fn lookup(
+        cache: &mut deadpool_redis::Connection,
+        token: &str,
+    ) -> Result<Option<Session>, RedisError> {
+        let digest = hash_token(token);
+        let id: Option<String> = cache.get(Self::token_key(&digest)).await?;
+        let Some(id) = id else {
+            return Ok(None);
+        };
+        let fields: HashMap<String, String> = cache.hgetall(Self::session_key(&id)).await?;
+        let is_current = fields
+            .get("token")
+            .is_some_and(|current| tokens_match(current, &digest));
+        Ok(Self::from_fields(&fields).filter(|_| is_current))
+    }
+
+    // Seconds until the absolute timeout, and until the session goes idle
+    fn remaining(session: &Session) -> (usize, usize) {
+        let remaining = (session.expires_at.and_utc().timestamp() - Utc::now().timestamp()).max(0);
+        let idle = remaining.min(session_timeouts().idle.into());
+        (remaining as usize, idle as usize)
+    }
+
     pub async fn create(
         cache: &mut deadpool_redis::Connection,
-        session_id: String,
-        user_id: i32,
+        token: String,
+        refresh_token: String,
+        new_session: NewSession,
     ) -> Result<(), RedisError> {
-        cache
-            .set_ex::<String, i32, ()>(
-                format!("sessions/{}", session_id),
-                user_id,
-                3 * 60 * 60, /*3h*/
+        let timeouts = session_timeouts();
+        let id: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(16)
+            .map(char::from)
+            .collect();
+        let created_at = Utc::now().timestamp();
+        let mut fields = vec![
+            ("id", id.clone()),
+            ("user_id", new_session.user_id.to_string()),
+            ("token", hash_token(&token)),
+            ("created_at", created_at.to_string()),
+            ("last_seen_at", created_at.to_string()),
+            (
+                "expires_at",
+                (created_at + i64::from(timeouts.absolute)).to_string(),
+            ),
+        ];
+        if let Some(ip) = new_session.ip {
+            fields.push(("ip", ip));
+        }
+        if let Some(user_agent) = new_session.user_agent {
+            fields.push(("user_agent", user_agent));
+        }
+
+        // Every login pushes the index's expiry forward, so it never expires
+        // before one of the sessions it tracks.
+        let key = Self::session_key(&id);
+        let user_sessions = Self::user_sessions_key(new_session.user_id);
+        let absolute = timeouts.absolute as usize;
+        deadpool_redis::redis::pipe()
+            .atomic()
+            .hset_multiple(&key, &fields)
+            .ignore()
+            .expire(&key, absolute)
+            .ignore()
+            .set_ex(
+                Self::token_key(&hash_token(&token)),
+                &id,
+                timeouts.idle.min(timeouts.absolute) as usize,
             )
+            .ignore()
+            .set_ex(
+                Self::refresh_token_key(&hash_token(&refresh_token)),
+                &id,
+                absolute,
+            )
+            .ignore()
+            .sadd(&user_sessions, &id)
+            .ignore()
+            .expire(&user_sessions, absolute)
+            .ignore()
+            .query_async(cache)
+            .await
+    }
+
+    // Returns the user of the session, records it as seen now and extends its
+    // idle timeout, up to the absolute one
+    pub async fn touch(
+        cache: &mut deadpool_redis::Connection,
+        token: &str,
+    ) -> Result<Option<i32>, RedisError> {
+        let Some(session) = Self::lookup(cache, token).await? else {
+            return Ok(None);
+        };
+        let (remaining, idle) = Self::remaining(&session);
+        if idle == 0 {
+            return Ok(None);
+        }
+
+        // Expiring the session again keeps the last seen field from outliving
+        // a session that timed out in the meantime.
+        let key = Self::session_key(&session.id);
+        deadpool_redis::redis::pipe()
+            .atomic()
+            .expire(Self::token_key(&hash_token(token)), idle)
+            .ignore()
+            .hset(&key, "last_seen_at", Utc::now().timestamp().to_string())
+            .ignore()
+            .expire(&key, remaining)
+            .ignore()
+            .query_async::<_, ()>(cache)
             .await?;
+        Ok(Some(session.user_id))
+    }
+
+    pub async fn find(
+        cache: &mut deadpool_redis::Connection,
+        id: &str,
+    ) -> Result<Option<Session>, RedisError> {
+        let fields: HashMap<String, String> = cache.hgetall(Self::session_key(id)).await?;
+        Ok(Self::from_fields(&fields))
+    }
+
+    pub async fn find_by_token(
+        cache: &mut deadpool_redis::Connection,
+        token: &str,
+    ) -> Result<Option<Session>, RedisError> {
+        Self::lookup(cache, token).await
+    }
+
+    pub async fn find_by_user(
+        cache: &mut deadpool_redis::Connection,
+        user_id: i32,
+    ) -> Result<Vec<Session>, RedisError> {
+        let user_sessions = Self::user_sessions_key(user_id);
+        let ids: Vec<String> = cache.smembers(&user_sessions).await?;
+
+        let mut sessions = Vec::with_capacity(ids.len());
+        for id in ids {
+            match Self::find(cache, &id).await? {
+                Some(session) => sessions.push(session),
+                None => cache.srem::<_, _, ()>(&user_sessions, id).await?,
+            }
+        }
+        sessions.sort_by_key(|session| Reverse(session.last_seen_at));
+        Ok(sessions)
+    }
+
+    // Rotates both tokens of the session the refresh token belongs to. A
+    // refresh token works once; reusing it revokes the session.
+    pub async fn refresh(
+        cache: &mut deadpool_redis::Connection,
+        refresh_token: &str,
+        token: String,
+        new_refresh_token: String,
+    ) -> Result<bool, RedisError> {
+        let id: Option<String> = cache
+            .get(Self::refresh_token_key(&hash_token(refresh_token)))
+            .await?;
+        let Some(id) = id else {
+            return Ok(false);
+        };
+        let key = Self::session_key(&id);
+        let fields: HashMap<String, String> = cache.hgetall(&key).await?;
+        let (Some(session), Some(old_token)) = (Self::from_fields(&fields), fields.get("token"))
+        else {
+            return Ok(false);
+        };
+        let (remaining, idle) = Self::remaining(&session);
+        if idle == 0 {
+            return Ok(false);
+        }
+
+        let claimed: Option<String> = deadpool_redis::redis::cmd("SET")
+            .arg(Self::used_refresh_token_key(&hash_token(refresh_token)))
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(remaining)
+            .query_async(cache)
+            .await?;
+        if claimed.is_none() {
+            Self::delete_by_id(cache, &id, session.user_id).await?;
+            return Ok(false);
+        }
+
+        deadpool_redis::redis::pipe()
+            .atomic()
+            .del(Self::token_key(old_token))
+            .ignore()
+            .set_ex(Self::token_key(&hash_token(&token)), &id, idle)
+            .ignore()
+            .set_ex(
+                Self::refresh_token_key(&hash_token(&new_refresh_token)),
+                &id,
+                remaining,
+            )
+            .ignore()
+            .hset_multiple(
+                &key,
+                &[
+                    ("token", hash_token(&token)),
+                    ("last_seen_at", Utc::now().timestamp().to_string()),
+                ],
+            )
+            .ignore()
+            .expire(&key, remaining)
+            .ignore()
+            .query_async::<_, ()>(cache)
+            .await?;
+        Ok(true)
+    }
+
+    pub async fn delete(
+        cache: &mut deadpool_redis::Connection,
+        token: &str,
+        user_id: i32,
+    ) -> Result<(), RedisError> {
+        if let Some(session) = Self::lookup(cache, token).await? {
+            Self::delete_by_id(cache, &session.id, user_id).await?;
+        }
         Ok(())
     }
+
+    pub async fn delete_by_id(
+        cache: &mut deadpool_redis::Connection,
+        id: &str,
+        user_id: i32,
+    ) -> Result<bool, RedisError> {
+        let key = Self::session_key(id);
+        let fields: HashMap<String, String> = cache.hgetall(&key).await?;
+        if fields.get("user_id") != Some(&user_id.to_string()) {
+            return Ok(false);
+        }
+
+        let mut pipe = deadpool_redis::redis::pipe();
+        pipe.atomic()
+            .del(&key)
+            .ignore()
+            .srem(Self::user_sessions_key(user_id), id)
+            .ignore();
+        if let Some(token) = fields.get("token") {
+            pipe.del(Self::token_key(token)).ignore();
+        }
+        pipe.query_async::<_, ()>(cache).await?;
+        Ok(true)
+    }
+
+    pub async fn delete_all(
+        cache: &mut deadpool_redis::Connection,
+        user_id: i32,
+    ) -> Result<(), RedisError> {
+        let user_sessions = Self::user_sessions_key(user_id);
+        let ids: Vec<String> = cache.smembers(&user_sessions).await?;
+
+        let mut keys = vec![user_sessions];
+        for id in ids {
+            let key = Self::session_key(&id);
+            let token: Option<String> = cache.hget(&key, "token").await?;
+            keys.extend(token.map(|token| Self::token_key(&token)));
+            keys.push(key);
+        }
+        cache.del(keys).await
+    }
 }
@@ -0,0 +1,290 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use diesel::QueryResult;
+use diesel_async::AsyncPgConnection;
+use serde::Serialize;
+
+use crate::{
+    models::{Crate, CrateDependency, CrateVersion, DependencyKind},
+    repositories::{CrateDependencyRepository, CrateVersionRepository},
+    semver::{self, Version, VersionReq},
+};
+
+#[derive(Serialize)]
+pub struct Node {
+    pub crate_id: i32,
+    pub code: String,
+    pub version: String,
+    pub req: Option<String>,
+    pub kind: Option<DependencyKind>,
+    pub optional: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub duplicate: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub cycle: bool,
+    pub dependencies: Vec<Node>,
+}
+
+#[derive(Serialize)]
+pub struct Unsatisfiable {
+    pub crate_id: i32,
+    pub code: String,
+    pub req: String,
+    pub required_by: String,
+    pub available: Vec<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub deleted: bool,
+}
+
+#[derive(Serialize)]
+pub struct Conflict {
+    pub crate_id: i32,
+    pub code: String,
+    pub versions: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct Resolution {
+    pub tree: Node,
+    pub cycles: Vec<Vec<String>>,
+    pub unsatisfiable: Vec<Unsatisfiable>,
+    pub conflicts: Vec<Conflict>,
+}
+
+// Dev-dependencies are only followed for the crate being resolved, like Cargo.
+fn is_followed(dependency: &CrateDependency, is_root: bool) -> bool {
+    is_root || dependency.kind != DependencyKind::Dev
+}
+
+fn select<'a>(versions: &'a [CrateVersion], req: &str) -> Option<&'a CrateVersion> {
+    let req: VersionReq = req.parse().ok()?;
+    versions
+        .iter()
+        .filter(|v| !v.yanked)
+        .filter_map(|v| v.version.parse::<Version>().ok().map(|parsed| (parsed, v)))
+        .filter(|(parsed, _)| req.matches(parsed))
+        .max_by(|(a, _), (b, _)| a.cmp_precedence(b))
+        .map(|(_, v)| v)
+}
+
+struct Graph {
+    root: CrateVersion,
+    root_crate: Crate,
+    crates: HashMap<i32, Crate>,
+    versions: HashMap<i32, Vec<CrateVersion>>,
+    dependencies: HashMap<i32, Vec<CrateDependency>>,
+}
+
+impl Graph {
+    async fn load(
+        c: &mut AsyncPgConnection,
+        root_crate: Crate,
+        root: CrateVersion,
+    ) -> QueryResult<Self> {
+        let mut graph = Graph {
+            root: root.clone(),
+            root_crate,
+            crates: HashMap::new(),
+            versions: HashMap::new(),
+            dependencies: HashMap::new(),
+        };
+        let mut frontier = vec![root];
+
+        while !frontier.is_empty() {
+            let ids: Vec<i32> = frontier.iter().map(|v| v.id).collect();
+            for (dependency, krate) in CrateDependencyRepository::find_by_versions(c, ids).await? {
+                graph
+                    .dependencies
+                    .entry(dependency.crate_version_id)
+                    .or_default()
+                    .push(dependency);
+                graph.crates.insert(krate.id, krate);
+            }
+
+            let missing: Vec<i32> = frontier
+                .iter()
+                .flat_map(|v| graph.followed(v))
+                .map(|d| d.dependency_id)
+                .filter(|id| !graph.versions.contains_key(id))
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect();
+            for id in &missing {
+                graph.versions.insert(*id, vec![]);
+            }
+            // Deleted crates offer no versions, so depending on one is unsatisfiable.
+            let live: Vec<i32> = missing
+                .into_iter()
+                .filter(|id| !graph.is_deleted(*id))
+                .collect();
+            for version in CrateVersionRepository::find_by_crates(c, live).await? {
+                graph
+                    .versions
+                    .entry(version.crate_id)
+                    .or_default()
+                    .push(version);
+            }
+
+            frontier = frontier
+                .iter()
+                .flat_map(|v| graph.followed(v))
+                .filter_map(|d| select(&graph.versions[&d.dependency_id], &d.req))
+                .filter(|v| !graph.dependencies.contains_key(&v.id))
+                .map(|v| (v.id, v.clone()))
+                .collect::<BTreeMap<_, _>>()
+                .into_values()
+                .collect();
+            for version in &frontier {
+                graph.dependencies.entry(version.id).or_default();
+            }
+        }
+
+        Ok(graph)
+    }
+
+    fn followed<'a>(&'a self, version: &CrateVersion) -> Vec<&'a CrateDependency> {
+        let is_root = version.id == self.root.id;
+        self.dependencies
+            .get(&version.id)
+            .map(|deps| deps.iter().filter(|d| is_followed(d, is_root)).collect())
+            .unwrap_or_default()
+    }
+
+    fn is_deleted(&self, crate_id: i32) -> bool {
+        self.crates
+            .get(&crate_id)
+            .is_some_and(|c| c.deleted_at.is_some())
+    }
+
+    fn code(&self, crate_id: i32) -> &str {
+        if crate_id == self.root_crate.id {
+            return &self.root_crate.code;
+        }
+        self.crates
+            .get(&crate_id)
+            .map(|c| c.code.as_str())
+            .unwrap_or_default()
+    }
+
+    fn label(&self, version: &CrateVersion) -> String {
+        format!("{} {}", self.code(version.crate_id), version.version)
+    }
+}
+
+struct Walker<'a> {
+    graph: &'a Graph,
+    path: Vec<i32>,
+    expanded: HashSet<i32>,
+    resolved: BTreeMap<i32, BTreeSet<String>>,
+    cycles: Vec<Vec<String>>,
+    unsatisfiable: Vec<Unsatisfiable>,
+}
+
+impl<'a> Walker<'a> {
+    fn walk(&mut self, version: &'a CrateVersion, dependency: Option<&CrateDependency>) -> Node {
+        let mut node = Node {
+            crate_id: version.crate_id,
+            code: self.graph.code(version.crate_id).to_owned(),
+            version: version.version.clone(),
+            req: dependency.map(|d| d.req.clone()),
+            kind: dependency.map(|d| d.kind),
+            optional: dependency.is_some_and(|d| d.optional),
+            duplicate: false,
+            cycle: false,
+            dependencies: vec![],
+        };
+        self.resolved
+            .entry(version.crate_id)
+            .or_default()
+            .insert(version.version.clone());
+
+        if let Some(start) = self.path.iter().position(|id| *id == version.id) {
+            let mut cycle: Vec<String> = self.path[start..]
+                .iter()
+                .map(|id| self.label_of(*id))
+                .collect();
+            cycle.push(self.graph.label(version));
+            self.cycles.push(cycle);
+            node.cycle = true;
+            return node;
+        }
+        if !self.expanded.insert(version.id) {
+            node.duplicate = true;
+            return node;
+        }
+
+        self.path.push(version.id);
+        for dependency in self.graph.followed(version) {
+            let candidates = &self.graph.versions[&dependency.dependency_id];
+            match select(candidates, &dependency.req) {
+                Some(selected) => {
+                    let child = self.walk(selected, Some(dependency));
+                    node.dependencies.push(child);
+                }
+                None => self.unsatisfiable.push(Unsatisfiable {
+                    crate_id: dependency.dependency_id,
+                    code: self.graph.code(dependency.dependency_id).to_owned(),
+                    req: dependency.req.clone(),
+                    required_by: self.graph.label(version),
+                    available: candidates.iter().map(|v| v.version.clone()).collect(),
+                    deleted: self.graph.is_deleted(dependency.dependency_id),
+                }),
+            }
+        }
+        self.path.pop();
+
+        node
+    }
+
+    fn label_of(&self, version_id: i32) -> String {
+        if version_id == self.graph.root.id {
+            return self.graph.label(&self.graph.root);
+        }
+        self.graph
+            .versions
+            .values()
+            .flatten()
+            .find(|v| v.id == version_id)
+            .map(|v| self.graph.label(v))
+            .unwrap_or_default()
+    }
+}
+
+pub async fn resolve(
+    c: &mut AsyncPgConnection,
+    root_crate: Crate,
+    root: CrateVersion,
+) -> QueryResult<Resolution> {
+    let graph = Graph::load(c, root_crate, root).await?;
+    let mut walker = Walker {
+        graph: &graph,
+        path: vec![],
+        expanded: HashSet::new(),
+        resolved: BTreeMap::new(),
+        cycles: vec![],
+        unsatisfiable: vec![],
+    };
+    let tree = walker.walk(&graph.root, None);
+
+    let conflicts = walker
+        .resolved
+        .iter()
+        .filter(|(_, versions)| versions.len() > 1)
+        .map(|(crate_id, versions)| {
+            let mut versions: Vec<String> = versions.iter().cloned().collect();
+            versions.sort_by(|a, b| semver::compare(a, b));
+            Conflict {
+                crate_id: *crate_id,
+                code: graph.code(*crate_id).to_owned(),
+                versions,
+            }
+        })
+        .collect();
+
+    Ok(Resolution {
+        tree,
+        cycles: walker.cycles,
+        unsatisfiable: walker.unsatisfiable,
+        conflicts,
+    })
+}
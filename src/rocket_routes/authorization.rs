@@ -1,37 +1,78 @@
 use crate::{
-    auth::{authorize_user, Credentials},
-    models::User,
-    repositories::{SessionRepository, UserRepository},
-    rocket_routes::{server_error, CacheConn, DbConn},
+    auth::{
+        authorize_user, generate_api_token, generate_token, hash_token, Credentials, RefreshRequest,
+    },
+    models::{ApiTokenRequest, NewApiToken, NewSession, Session, User},
+    repositories::{ApiTokenRepository, SessionRepository, UserRepository},
+    rocket_routes::{AdminUser, ApiError, CacheConn, ClientInfo, DbConn, JsonBody, SessionToken},
+};
+use rocket::{
+    http::Status,
+    response::status::{Custom, NoContent},
+    serde::json::{json, Value},
 };
-use rocket::serde::json::{json, Json, Value};
-use rocket::{http::Status, response::status::Custom};
 use rocket_db_pools::Connection;
+use validator::Validate;
 
 #[rocket::post("/login", format = "json", data = "<credentials>")]
 pub async fn login(
     mut db: Connection<DbConn>,
     mut cache: Connection<CacheConn>,
-    credentials: Json<Credentials>,
-) -> Result<Value, Custom<Value>> {
+    credentials: JsonBody<Credentials>,
+    client: ClientInfo,
+) -> Result<Value, ApiError> {
     let user = UserRepository::find_by_name(&mut db, &credentials.username)
         .await
         .map_err(|e| match e {
             diesel::result::Error::NotFound => {
-                Custom(Status::Unauthorized, json!("Wrong credentials"))
+                ApiError::Unauthorized("Wrong credentials".to_owned())
             }
-            _ => server_error(e.into()),
+            _ => e.into(),
         })?;
 
-    let session_id = authorize_user(&user, credentials.into_inner())
-        .map_err(|_| Custom(Status::Unauthorized, json!("Wrong credentials")))?;
+    let session_id = authorize_user(&user, credentials.into_inner())?;
+    let refresh_token = generate_token();
 
-    SessionRepository::create(&mut cache, session_id.clone(), user.id)
-        .await
-        .map_err(|e| server_error(e.into()))?;
+    SessionRepository::create(
+        &mut cache,
+        session_id.clone(),
+        refresh_token.clone(),
+        NewSession {
+            user_id: user.id,
+            ip: client.ip,
+            user_agent: client.user_agent,
+        },
+    )
+    .await?;
 
     Ok(json!({
-        "token": session_id
+        "token": session_id,
+        "refresh_token": refresh_token
+    }))
+}
+
+#[rocket::post("/token/refresh", format = "json", data = "<request>")]
+pub async fn refresh_token(
+    mut cache: Connection<CacheConn>,
+    request: JsonBody<RefreshRequest>,
+) -> Result<Value, ApiError> {
+    let token = generate_token();
+    let refresh_token = generate_token();
+
+    if !SessionRepository::refresh(
+        &mut cache,
+        &request.refresh_token,
+        token.clone(),
+        refresh_token.clone(),
+    )
+    .await?
+    {
+        return Err(ApiError::Unauthorized("Invalid refresh token".to_owned()));
+    }
+
+    Ok(json!({
+        "token": token,
+        "refresh_token": refresh_token
     }))
 }
 
@@ -39,3 +80,135 @@ pub async fn login(
 pub async fn me(user: User) -> Value {
     json!(user)
 }
+
+fn sessions_json(sessions: Vec<Session>, current: Option<&str>) -> Value {
+    let sessions: Vec<Value> = sessions
+        .into_iter()
+        .map(|session| {
+            let mut json = json!(session);
+            json["current"] = json!(current == Some(session.id.as_str()));
+            json
+        })
+        .collect();
+    json!(sessions)
+}
+
+#[rocket::get("/me/sessions")]
+pub async fn get_my_sessions(
+    mut cache: Connection<CacheConn>,
+    session: SessionToken,
+    user: User,
+) -> Result<Value, ApiError> {
+    let current = SessionRepository::find_by_token(&mut cache, &session.0).await?;
+    let sessions = SessionRepository::find_by_user(&mut cache, user.id).await?;
+    Ok(sessions_json(
+        sessions,
+        current.as_ref().map(|current| current.id.as_str()),
+    ))
+}
+
+#[rocket::delete("/me/sessions/<id>")]
+pub async fn delete_my_session(
+    mut cache: Connection<CacheConn>,
+    id: &str,
+    user: User,
+) -> Result<NoContent, ApiError> {
+    if SessionRepository::delete_by_id(&mut cache, id, user.id).await? {
+        Ok(NoContent)
+    } else {
+        Err(ApiError::not_found())
+    }
+}
+
+#[rocket::get("/me/tokens")]
+pub async fn get_my_tokens(mut db: Connection<DbConn>, user: User) -> Result<Value, ApiError> {
+    let tokens = ApiTokenRepository::find_by_user(&mut db, &user).await?;
+    Ok(json!(tokens))
+}
+
+#[rocket::post("/me/tokens", format = "json", data = "<new_token>")]
+pub async fn create_my_token(
+    mut db: Connection<DbConn>,
+    new_token: JsonBody<ApiTokenRequest>,
+    user: User,
+) -> Result<Custom<Value>, ApiError> {
+    new_token.validate()?;
+    let new_token = new_token.into_inner();
+
+    let mut scopes: Vec<String> = new_token.scopes.iter().map(ToString::to_string).collect();
+    scopes.sort();
+    scopes.dedup();
+
+    // The token is only ever shown here, the database keeps its hash
+    let token = generate_api_token();
+    let api_token = ApiTokenRepository::create(
+        &mut db,
+        NewApiToken {
+            user_id: user.id,
+            name: new_token.name,
+            token_hash: hash_token(&token),
+            scopes: scopes.join(" "),
+            expires_at: new_token.expires_at,
+        },
+    )
+    .await?;
+
+    let mut json = json!(api_token);
+    json["token"] = json!(token);
+    Ok(Custom(Status::Created, json))
+}
+
+#[rocket::delete("/me/tokens/<id>")]
+pub async fn delete_my_token(
+    mut db: Connection<DbConn>,
+    id: i32,
+    user: User,
+) -> Result<NoContent, ApiError> {
+    match ApiTokenRepository::delete(&mut db, id, &user).await? {
+        0 => Err(ApiError::not_found()),
+        _ => Ok(NoContent),
+    }
+}
+
+#[rocket::post("/logout")]
+pub async fn logout(
+    mut cache: Connection<CacheConn>,
+    session: SessionToken,
+    user: User,
+) -> Result<NoContent, ApiError> {
+    SessionRepository::delete(&mut cache, &session.0, user.id).await?;
+    Ok(NoContent)
+}
+
+#[rocket::post("/logout-all")]
+pub async fn logout_all(
+    mut cache: Connection<CacheConn>,
+    user: User,
+) -> Result<NoContent, ApiError> {
+    SessionRepository::delete_all(&mut cache, user.id).await?;
+    Ok(NoContent)
+}
+
+#[rocket::get("/users/<id>/sessions")]
+pub async fn get_user_sessions(
+    mut db: Connection<DbConn>,
+    mut cache: Connection<CacheConn>,
+    id: i32,
+    _admin: AdminUser,
+) -> Result<Value, ApiError> {
+    let user = UserRepository::find_by_id(&mut db, id).await?;
+    let sessions = SessionRepository::find_by_user(&mut cache, user.id).await?;
+    Ok(sessions_json(sessions, None))
+}
+
+#[rocket::delete("/users/<id>/sessions")]
+pub async fn delete_user_sessions(
+    mut db: Connection<DbConn>,
+    mut cache: Connection<CacheConn>,
+    id: i32,
+    _admin: AdminUser,
+) -> Result<NoContent, ApiError> {
+    let user = UserRepository::find_by_id(&mut db, id).await?;
+    SessionRepository::delete_all(&mut cache, user.id).await?;
+    Ok(NoContent)
+}
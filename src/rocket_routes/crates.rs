@@ -1,78 +1,609 @@
-use crate::models::User;
-use crate::rocket_routes::{server_error, DbConn, EditorUser};
+use crate::rocket_routes::{
+    apply_merge_patch, cursor_response, page_response, parse_cursor, parse_timestamp, AdminUser,
+    ApiError, CratesRead, CratesWrite, DbConn, EditorUser, IfMatch, JsonBody, Scoped, Versioned,
+};
 use crate::{
-    models::{Crate, NewCrate},
-    repositories::CrateRepository,
+    graph::{CrateGraph, GraphFilter, GraphFormat},
+    models::{
+        Crate, CrateFilter, CrateVersion, NewCrate, NewCrateDependency, NewCrateVersion,
+        PageRequest, Sort, SortField, SortOrder, User,
+    },
+    repositories::{
+        CrateDependencyRepository, CrateRepository, CrateVersionRepository, HistoryRepository,
+        RustaceanRepository,
+    },
+    resolver,
+    semver::VersionReq,
 };
+use chrono::NaiveDateTime;
 use rocket::{
-    http::Status,
+    http::{ContentType, Status},
     response::status::{Custom, NoContent},
-    serde::json::{json, Json, Value},
+    serde::json::{json, Value},
 };
 use rocket_db_pools::Connection;
+use validator::Validate;
+
+fn missing_rustacean(e: diesel::result::Error) -> ApiError {
+    match e {
+        diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::ForeignKeyViolation,
+            _,
+        ) => ApiError::Validation("Rustacean does not exist".to_owned()),
+        _ => e.into(),
+    }
+}
+
+// Deleted rustaceans still satisfy the foreign key, so look for a live one.
+async fn check_rustacean(db: &mut Connection<DbConn>, id: i32) -> Result<(), ApiError> {
+    match RustaceanRepository::find(db, id).await {
+        Ok(_) => Ok(()),
+        Err(diesel::result::Error::NotFound) => {
+            Err(ApiError::Validation("Rustacean does not exist".to_owned()))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn code_taken(db: &mut Connection<DbConn>, code: &str) -> ApiError {
+    match CrateRepository::find_by_code(db, code).await {
+        Ok(existing) => ApiError::Duplicate("Crate code is already taken".to_owned(), existing.id),
+        Err(e) => e.into(),
+    }
+}
+
+async fn find_crate_version(
+    db: &mut Connection<DbConn>,
+    id: i32,
+    version: &str,
+) -> Result<CrateVersion, ApiError> {
+    CrateVersionRepository::find_by_version(db, id, version)
+        .await
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => ApiError::NotFound("Version not found".to_owned()),
+            _ => e.into(),
+        })
+}
+
+#[derive(rocket::FromForm)]
+pub struct CrateListQuery {
+    page: Option<i64>,
+    per_page: Option<i64>,
+    sort: Option<SortField>,
+    order: Option<SortOrder>,
+    rustacean_id: Option<i32>,
+    created_after: Option<String>,
+    cursor: Option<String>,
+}
+
+#[rocket::get("/crates?<query..>")]
+pub async fn get_crates(
+    mut db: Connection<DbConn>,
+    query: CrateListQuery,
+    _user: Scoped<CratesRead>,
+) -> Result<Versioned<Value>, ApiError> {
+    let filter = CrateFilter {
+        rustacean_id: query.rustacean_id,
+        created_after: query
+            .created_after
+            .as_deref()
+            .map(parse_timestamp)
+            .transpose()?,
+    };
+    let params = vec![
+        ("sort", query.sort.map(|v| v.as_str().to_owned())),
+        ("order", query.order.map(|v| v.as_str().to_owned())),
+        ("rustacean_id", query.rustacean_id.map(|v| v.to_string())),
+        ("created_after", query.created_after.clone()),
+    ];
+
+    let last_modified = CrateRepository::last_modified(&mut db).await?;
+
+    if let Some(cursor) = query.cursor.as_deref() {
+        if matches!(query.sort, Some(SortField::Name)) {
+            return Err(ApiError::Validation(
+                "Cursor pagination only supports sort=created_at".to_owned(),
+            ));
+        }
+        let cursor = parse_cursor(cursor)?;
+        let per_page = PageRequest::new(None, query.per_page).per_page;
+
+        return CrateRepository::find_after(
+            &mut db,
+            &filter,
+            query.order.unwrap_or_default(),
+            cursor,
+            per_page,
+        )
+        .await
+        .map(|r| Versioned::listing(last_modified, cursor_response("/crates", r, params)))
+        .map_err(ApiError::from);
+    }
+
+    let sort = Sort {
+        field: query.sort.unwrap_or_default(),
+        order: query.order.unwrap_or_default(),
+    };
+    let page = PageRequest::new(query.page, query.per_page);
+
+    CrateRepository::find_page(&mut db, &filter, sort, page)
+        .await
+        .map(|r| Versioned::listing(last_modified, page_response("/crates", r, params)))
+        .map_err(ApiError::from)
+}
+
+#[rocket::get("/crates/search?<q>&<page>&<per_page>")]
+pub async fn search_crates(
+    mut db: Connection<DbConn>,
+    q: &str,
+    page: Option<i64>,
+    per_page: Option<i64>,
+    _user: Scoped<CratesRead>,
+) -> Result<Value, ApiError> {
+    if q.trim().is_empty() {
+        return Err(ApiError::Validation(
+            "Search query must not be empty".to_owned(),
+        ));
+    }
+    let page = PageRequest::new(page, per_page);
+
+    CrateRepository::search(&mut db, q, page)
+        .await
+        .map(|r| page_response("/crates/search", r, vec![("q", Some(q.to_owned()))]))
+        .map_err(ApiError::from)
+}
+
+#[rocket::get("/crates/autocomplete?<prefix>&<limit>")]
+pub async fn autocomplete_crates(
+    mut db: Connection<DbConn>,
+    prefix: &str,
+    limit: Option<i64>,
+    _user: Scoped<CratesRead>,
+) -> Result<Value, ApiError> {
+    let prefix = prefix.trim();
+    if prefix.is_empty() {
+        return Err(ApiError::Validation("Prefix must not be empty".to_owned()));
+    }
 
-#[rocket::get("/crates")]
-pub async fn get_crates(mut db: Connection<DbConn>, _user: User) -> Result<Value, Custom<Value>> {
-    CrateRepository::find_all(&mut db, 100)
+    CrateRepository::autocomplete(&mut db, prefix, limit.unwrap_or(10).clamp(1, 50))
         .await
         .map(|r| json!(r))
-        .map_err(|e| server_error(e.into()))
+        .map_err(ApiError::from)
+}
+
+#[rocket::get("/crates/graph?<format>&<root>&<depth>&<rustacean_id>")]
+pub async fn get_crates_graph(
+    mut db: Connection<DbConn>,
+    format: Option<GraphFormat>,
+    root: Option<i32>,
+    depth: Option<u32>,
+    rustacean_id: Option<i32>,
+    _user: Scoped<CratesRead>,
+) -> Result<(ContentType, String), ApiError> {
+    let filter = GraphFilter {
+        root,
+        depth,
+        rustacean_id,
+    };
+    let graph = CrateGraph::load(&mut db, &filter)
+        .await
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => {
+                ApiError::NotFound("Root crate not found".to_owned())
+            }
+            _ => e.into(),
+        })?;
+
+    Ok(match format.unwrap_or_default() {
+        GraphFormat::Json => (ContentType::JSON, json!(graph).to_string()),
+        GraphFormat::Dot => (ContentType::new("text", "vnd.graphviz"), graph.to_dot()),
+    })
+}
+
+// The crate as it was at `as_of`, dated by the change that left it that way.
+async fn crate_as_of(
+    db: &mut Connection<DbConn>,
+    id: i32,
+    as_of: NaiveDateTime,
+) -> Result<Versioned<Value>, ApiError> {
+    let entry = HistoryRepository::find_as_of(db, "crates", id, as_of).await?;
+    match entry.after {
+        Some(mut after) if after["deleted_at"].is_null() => {
+            if let Some(fields) = after.as_object_mut() {
+                fields.remove("deleted_at");
+            }
+            Ok(Versioned::new(entry.created_at, after))
+        }
+        _ => Err(ApiError::not_found()),
+    }
 }
 
-#[rocket::get("/crates/<id>")]
+#[rocket::get("/crates/<id>?<as_of>")]
 pub async fn get_crate(
     mut db: Connection<DbConn>,
     id: i32,
-    _user: User,
-) -> Result<Value, Custom<Value>> {
+    as_of: Option<&str>,
+    _user: Scoped<CratesRead>,
+) -> Result<Versioned<Value>, ApiError> {
+    if let Some(as_of) = as_of {
+        return crate_as_of(&mut db, id, parse_timestamp(as_of)?).await;
+    }
+
     CrateRepository::find(&mut db, id)
         .await
-        .map(|r| json!(r))
-        .map_err(|e| match e {
-            diesel::result::Error::NotFound => Custom(Status::NotFound, json!("Not found")),
-            _ => server_error(e.into()),
-        })
+        .map(|r| Versioned::new(r.updated_at, json!(r)))
+        .map_err(ApiError::from)
+}
+
+// Codes match regardless of case and of `-` versus `_`, like the unique index.
+// Rocket sees `/crates/by-code/versions` as matching both this route and
+// `/crates/<id>/versions` (likewise `history`) and refuses to launch unless they
+// are ranked apart. Rank 1 tries the `<id>` routes first; they forward because
+// `by-code` is not a number, so the path still reaches this route.
+#[rocket::get("/crates/by-code/<code>", rank = 1)]
+pub async fn get_crate_by_code(
+    mut db: Connection<DbConn>,
+    code: &str,
+    _user: Scoped<CratesRead>,
+) -> Result<Versioned<Value>, ApiError> {
+    CrateRepository::find_by_code(&mut db, code)
+        .await
+        .map(|r| Versioned::new(r.updated_at, json!(r)))
+        .map_err(ApiError::from)
 }
 
 #[rocket::post("/crates", format = "json", data = "<new_crate>")]
 pub async fn crate_crate(
     mut db: Connection<DbConn>,
-    new_crate: Json<NewCrate>,
-    _user: EditorUser,
-) -> Result<Custom<Value>, Custom<Value>> {
-    CrateRepository::create(&mut db, new_crate.into_inner())
-        .await
-        .map(|r| Custom(Status::Created, json!(r)))
-        .map_err(|e| server_error(e.into()))
+    new_crate: JsonBody<NewCrate>,
+    user: Scoped<CratesWrite, EditorUser>,
+) -> Result<Custom<Value>, ApiError> {
+    new_crate.validate()?;
+    check_rustacean(&mut db, new_crate.rustacean_id).await?;
+    let code = new_crate.code.clone();
+
+    match CrateRepository::create(&mut db, new_crate.into_inner(), &user.0).await {
+        Ok(r) => Ok(Custom(Status::Created, json!(r))),
+        Err(diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::UniqueViolation,
+            _,
+        )) => Err(code_taken(&mut db, &code).await),
+        Err(e) => Err(missing_rustacean(e)),
+    }
 }
 
 #[rocket::put("/crates/<id>", format = "json", data = "<u_crate>")]
 pub async fn update_crate(
     mut db: Connection<DbConn>,
     id: i32,
-    u_crate: Json<Crate>,
-    _user: EditorUser,
-) -> Result<Value, Custom<Value>> {
-    CrateRepository::update(&mut db, id, u_crate.into_inner())
+    u_crate: JsonBody<Crate>,
+    if_match: IfMatch,
+    user: Scoped<CratesWrite, EditorUser>,
+) -> Result<Versioned<Value>, ApiError> {
+    u_crate.validate()?;
+    let updated_at = if_match.expected(CrateRepository::find(&mut db, id).await?.updated_at)?;
+
+    match save_crate(&mut db, id, u_crate.into_inner(), updated_at, &user.0).await? {
+        Some(r) => Ok(Versioned::new(r.updated_at, json!(r))),
+        None => Err(ApiError::modified()),
+    }
+}
+
+// `None` when the crate is no longer at `updated_at`.
+async fn save_crate(
+    db: &mut Connection<DbConn>,
+    id: i32,
+    u_crate: Crate,
+    updated_at: Option<NaiveDateTime>,
+    user: &User,
+) -> Result<Option<Crate>, ApiError> {
+    check_rustacean(db, u_crate.rustacean_id).await?;
+    let code = u_crate.code.clone();
+
+    match CrateRepository::update(db, id, u_crate, updated_at, user).await {
+        Ok(r) => Ok(Some(r)),
+        Err(diesel::result::Error::NotFound) if updated_at.is_some() => Ok(None),
+        Err(diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::UniqueViolation,
+            _,
+        )) => Err(code_taken(db, &code).await),
+        Err(e) => Err(missing_rustacean(e)),
+    }
+}
+
+// The patch is only written over the version it was applied to. Without
+// `If-Match`, a crate changed in the meantime is patched again, so concurrent
+// patches of different fields all stick.
+#[rocket::patch("/crates/<id>", data = "<patch>")]
+pub async fn patch_crate(
+    mut db: Connection<DbConn>,
+    id: i32,
+    patch: JsonBody<Value>,
+    if_match: IfMatch,
+    user: Scoped<CratesWrite, EditorUser>,
+) -> Result<Versioned<Value>, ApiError> {
+    let patch = patch.into_inner();
+    let mut current = CrateRepository::find(&mut db, id).await?;
+    let conditional = if_match.expected(current.updated_at)?.is_some();
+
+    loop {
+        let u_crate: Crate = apply_merge_patch(&current, patch.clone())?;
+        // A version from before SemVer was enforced stays until the client changes it.
+        if let Err(mut errors) = u_crate.validate() {
+            if u_crate.version == current.version {
+                errors.errors_mut().remove("version");
+            }
+            if !errors.is_empty() {
+                return Err(errors.into());
+            }
+        }
+
+        match save_crate(&mut db, id, u_crate, Some(current.updated_at), &user.0).await? {
+            Some(r) => return Ok(Versioned::new(r.updated_at, json!(r))),
+            None if conditional => return Err(ApiError::modified()),
+            None => current = CrateRepository::find(&mut db, id).await?,
+        }
+    }
+}
+
+#[rocket::put("/crates/by-code/<code>", format = "json", data = "<u_crate>")]
+pub async fn update_crate_by_code(
+    mut db: Connection<DbConn>,
+    code: &str,
+    u_crate: JsonBody<Crate>,
+    if_match: IfMatch,
+    user: Scoped<CratesWrite, EditorUser>,
+) -> Result<Versioned<Value>, ApiError> {
+    let id = CrateRepository::find_by_code(&mut db, code).await?.id;
+    update_crate(db, id, u_crate, if_match, user).await
+}
+
+#[rocket::delete("/crates/<id>?<cascade>")]
+pub async fn delete_crates(
+    mut db: Connection<DbConn>,
+    id: i32,
+    cascade: Option<bool>,
+    if_match: IfMatch,
+    user: Scoped<CratesWrite, EditorUser>,
+    admin: Option<Scoped<CratesWrite, AdminUser>>,
+) -> Result<NoContent, ApiError> {
+    let cascade = cascade.unwrap_or(false);
+    if cascade && admin.is_none() {
+        return Err(ApiError::Forbidden(
+            "Only admins can cascade deletes".to_owned(),
+        ));
+    }
+    let updated_at = if_match.expected(CrateRepository::find(&mut db, id).await?.updated_at)?;
+    if !cascade {
+        let dependents = CrateRepository::find_dependents(&mut db, id).await?;
+        if !dependents.is_empty() {
+            return Err(ApiError::Blocked(
+                "Crate is a dependency of other crates".to_owned(),
+                dependents,
+            ));
+        }
+    }
+
+    if CrateRepository::delete(&mut db, id, updated_at, &user.0).await? == 0 {
+        return Err(match updated_at {
+            Some(_) => ApiError::modified(),
+            None => ApiError::not_found(),
+        });
+    }
+    Ok(NoContent)
+}
+
+#[rocket::delete("/crates/by-code/<code>?<cascade>")]
+pub async fn delete_crate_by_code(
+    mut db: Connection<DbConn>,
+    code: &str,
+    cascade: Option<bool>,
+    if_match: IfMatch,
+    user: Scoped<CratesWrite, EditorUser>,
+    admin: Option<Scoped<CratesWrite, AdminUser>>,
+) -> Result<NoContent, ApiError> {
+    let id = CrateRepository::find_by_code(&mut db, code).await?.id;
+    delete_crates(db, id, cascade, if_match, user, admin).await
+}
+
+#[rocket::post("/crates/<id>/restore")]
+pub async fn restore_crate(
+    mut db: Connection<DbConn>,
+    id: i32,
+    admin: Scoped<CratesWrite, AdminUser>,
+) -> Result<Versioned<Value>, ApiError> {
+    let krate = CrateRepository::find_deleted(&mut db, id).await?;
+    match RustaceanRepository::find(&mut db, krate.rustacean_id).await {
+        Ok(_) => {}
+        Err(diesel::result::Error::NotFound) => {
+            return Err(ApiError::Conflict(
+                "Rustacean is deleted, restore it first".to_owned(),
+            ))
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    match CrateRepository::restore(&mut db, id, &admin.0).await {
+        Ok(r) => Ok(Versioned::new(r.updated_at, json!(r))),
+        Err(diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::UniqueViolation,
+            _,
+        )) => Err(code_taken(&mut db, &krate.code).await),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Newest first. Deleted crates keep their history until they are purged.
+#[rocket::get("/crates/<id>/history")]
+pub async fn get_crate_history(
+    mut db: Connection<DbConn>,
+    id: i32,
+    _user: Scoped<CratesRead>,
+) -> Result<Value, ApiError> {
+    let entries = HistoryRepository::find_by_record(&mut db, "crates", id).await?;
+    if entries.is_empty() {
+        return Err(ApiError::not_found());
+    }
+
+    Ok(json!(entries
+        .into_iter()
+        .map(|(entry, user)| {
+            let mut entry = json!(entry);
+            entry["username"] = json!(user.map(|u| u.username));
+            entry
+        })
+        .collect::<Vec<_>>()))
+}
+
+#[rocket::get("/crates/<id>/versions")]
+pub async fn get_crate_versions(
+    mut db: Connection<DbConn>,
+    id: i32,
+    _user: Scoped<CratesRead>,
+) -> Result<Value, ApiError> {
+    CrateRepository::find(&mut db, id).await?;
+
+    CrateVersionRepository::find_by_crate(&mut db, id)
         .await
         .map(|r| json!(r))
+        .map_err(ApiError::from)
+}
+
+#[rocket::post("/crates/<id>/versions", format = "json", data = "<new_version>")]
+pub async fn create_crate_version(
+    mut db: Connection<DbConn>,
+    id: i32,
+    new_version: JsonBody<NewCrateVersion>,
+    user: Scoped<CratesWrite, EditorUser>,
+) -> Result<Custom<Value>, ApiError> {
+    new_version.validate()?;
+    CrateRepository::find(&mut db, id).await?;
+    let mut new_version = new_version.into_inner();
+    new_version.crate_id = id;
+
+    CrateVersionRepository::create(&mut db, new_version, &user.0)
+        .await
+        .map(|r| Custom(Status::Created, json!(r)))
         .map_err(|e| match e {
-            diesel::result::Error::NotFound => Custom(Status::NotFound, json!("Not found")),
-            _ => server_error(e.into()),
+            diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                _,
+            ) => ApiError::Conflict("Version already exists".to_owned()),
+            _ => e.into(),
         })
 }
 
-#[rocket::delete("/crates/<id>")]
-pub async fn delete_crates(
+#[rocket::get("/crates/<id>/dependencies?<version>")]
+pub async fn get_crate_dependencies(
+    mut db: Connection<DbConn>,
+    id: i32,
+    version: Option<&str>,
+    _user: Scoped<CratesRead>,
+) -> Result<Value, ApiError> {
+    let krate = CrateRepository::find(&mut db, id).await?;
+    let crate_version = find_crate_version(&mut db, id, version.unwrap_or(&krate.version)).await?;
+
+    CrateDependencyRepository::find_by_version(&mut db, crate_version.id)
+        .await
+        .map(|r| {
+            json!(r
+                .into_iter()
+                .map(|(dependency, krate)| json!({ "dependency": dependency, "crate": krate }))
+                .collect::<Vec<_>>())
+        })
+        .map_err(ApiError::from)
+}
+
+#[rocket::post(
+    "/crates/<id>/versions/<version>/dependencies",
+    format = "json",
+    data = "<new_dependency>"
+)]
+pub async fn create_crate_dependency(
     mut db: Connection<DbConn>,
     id: i32,
-    _user: EditorUser,
-) -> Result<NoContent, Custom<Value>> {
-    CrateRepository::delete(&mut db, id)
+    version: &str,
+    new_dependency: JsonBody<NewCrateDependency>,
+    _user: Scoped<CratesWrite, EditorUser>,
+) -> Result<Custom<Value>, ApiError> {
+    new_dependency.req.parse::<VersionReq>().map_err(|e| {
+        ApiError::Validation(format!(
+            "Invalid requirement '{}': {}",
+            new_dependency.req, e
+        ))
+    })?;
+    if new_dependency.dependency_id == id {
+        return Err(ApiError::Validation(
+            "A crate cannot depend on itself".to_owned(),
+        ));
+    }
+    CrateRepository::find(&mut db, id).await?;
+    let crate_version = CrateVersionRepository::find_by_version(&mut db, id, version).await?;
+    CrateRepository::find(&mut db, new_dependency.dependency_id)
         .await
-        .map(|_| NoContent)
         .map_err(|e| match e {
-            diesel::result::Error::NotFound => Custom(Status::NotFound, json!("Not found")),
-            _ => server_error(e.into()),
+            diesel::result::Error::NotFound => {
+                ApiError::Validation("Dependency crate does not exist".to_owned())
+            }
+            _ => e.into(),
+        })?;
+    let mut new_dependency = new_dependency.into_inner();
+    new_dependency.crate_version_id = crate_version.id;
+
+    CrateDependencyRepository::create(&mut db, new_dependency)
+        .await
+        .map(|r| Custom(Status::Created, json!(r)))
+        .map_err(|e| match e {
+            diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::ForeignKeyViolation,
+                _,
+            ) => ApiError::Validation("Dependency crate does not exist".to_owned()),
+            diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                _,
+            ) => ApiError::Conflict("Dependency already exists".to_owned()),
+            _ => e.into(),
+        })
+}
+
+#[rocket::get("/crates/<id>/dependents?<all_versions>")]
+pub async fn get_crate_dependents(
+    mut db: Connection<DbConn>,
+    id: i32,
+    all_versions: Option<bool>,
+    _user: Scoped<CratesRead>,
+) -> Result<Value, ApiError> {
+    CrateRepository::find(&mut db, id).await?;
+
+    CrateDependencyRepository::find_dependents(&mut db, id, all_versions.unwrap_or(false))
+        .await
+        .map(|r| {
+            json!(r
+                .into_iter()
+                .map(|(dependency, crate_version, krate)| {
+                    json!({
+                        "dependency": dependency,
+                        "crate": krate,
+                        "version": crate_version.version,
+                    })
+                })
+                .collect::<Vec<_>>())
         })
+        .map_err(ApiError::from)
+}
+
+#[rocket::get("/crates/<id>/versions/<version>/resolve")]
+pub async fn resolve_crate_version(
+    mut db: Connection<DbConn>,
+    id: i32,
+    version: &str,
+    _user: Scoped<CratesRead>,
+) -> Result<Value, ApiError> {
+    let krate = CrateRepository::find(&mut db, id).await?;
+    let crate_version = find_crate_version(&mut db, id, version).await?;
+
+    resolver::resolve(&mut db, krate, crate_version)
+        .await
+        .map(|r| json!(r))
+        .map_err(ApiError::from)
 }
@@ -0,0 +1,298 @@
+use std::fmt;
+
+use rand::{distributions::Alphanumeric, Rng};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::{ContentType, Status},
+    response::{self, Responder},
+    Data, Request, Response,
+};
+use rocket_db_pools::deadpool_redis::redis::RedisError;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{models::Crate, semver::SemVerError};
+
+#[derive(Clone, Serialize)]
+pub struct FieldError {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    pub message: String,
+}
+
+#[derive(Clone)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    // A unique value already taken by the row with the given id.
+    Duplicate(String, i32),
+    // A delete refused because other crates still reference the target.
+    Blocked(String, Vec<Crate>),
+    // An `If-Match` that no longer names the current version.
+    PreconditionFailed(String),
+    Validation(String),
+    Invalid(String, Vec<FieldError>),
+    // A database or cache the request needs is unreachable.
+    Unavailable(String),
+    // Logged, never shown to the client.
+    Internal(String),
+}
+
+impl ApiError {
+    pub fn not_found() -> Self {
+        ApiError::NotFound("Not found".to_owned())
+    }
+
+    pub fn modified() -> Self {
+        ApiError::PreconditionFailed("Resource has been modified".to_owned())
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            ApiError::BadRequest(_) => Status::BadRequest,
+            ApiError::Unauthorized(_) => Status::Unauthorized,
+            ApiError::Forbidden(_) => Status::Forbidden,
+            ApiError::NotFound(_) => Status::NotFound,
+            ApiError::Conflict(_) | ApiError::Duplicate(_, _) | ApiError::Blocked(_, _) => {
+                Status::Conflict
+            }
+            ApiError::PreconditionFailed(_) => Status::PreconditionFailed,
+            ApiError::Validation(_) | ApiError::Invalid(_, _) => Status::UnprocessableEntity,
+            ApiError::Unavailable(_) => Status::ServiceUnavailable,
+            ApiError::Internal(_) => Status::InternalServerError,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Duplicate(_, _) => "already_exists",
+            ApiError::Blocked(_, _) => "still_referenced",
+            ApiError::PreconditionFailed(_) => "precondition_failed",
+            ApiError::Validation(_) | ApiError::Invalid(_, _) => "validation_failed",
+            ApiError::Unavailable(_) => "service_unavailable",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    fn detail(&self) -> &str {
+        match self {
+            ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::Conflict(message)
+            | ApiError::Duplicate(message, _)
+            | ApiError::Blocked(message, _)
+            | ApiError::PreconditionFailed(message)
+            | ApiError::Validation(message)
+            | ApiError::Invalid(message, _)
+            | ApiError::Unavailable(message) => message,
+            ApiError::Internal(_) => "Internal server error",
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Internal(e) => write!(f, "{}", e),
+            _ => write!(f, "{}", self.detail()),
+        }
+    }
+}
+
+impl From<diesel::result::Error> for ApiError {
+    fn from(e: diesel::result::Error) -> Self {
+        use diesel::result::{DatabaseErrorKind, Error};
+
+        match e {
+            Error::NotFound => ApiError::not_found(),
+            Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                ApiError::Conflict("Resource already exists".to_owned())
+            }
+            Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => {
+                ApiError::Conflict("Resource is referenced by other records".to_owned())
+            }
+            Error::DatabaseError(
+                DatabaseErrorKind::NotNullViolation | DatabaseErrorKind::CheckViolation,
+                info,
+            ) => ApiError::Validation(info.message().to_owned()),
+            _ => ApiError::Internal(e.to_string()),
+        }
+    }
+}
+
+impl From<RedisError> for ApiError {
+    fn from(e: RedisError) -> Self {
+        ApiError::Internal(e.to_string())
+    }
+}
+
+impl From<argon2::password_hash::Error> for ApiError {
+    fn from(e: argon2::password_hash::Error) -> Self {
+        match e {
+            argon2::password_hash::Error::Password => {
+                ApiError::Unauthorized("Wrong credentials".to_owned())
+            }
+            _ => ApiError::Internal(e.to_string()),
+        }
+    }
+}
+
+impl From<SemVerError> for ApiError {
+    fn from(e: SemVerError) -> Self {
+        ApiError::Validation(e.to_string())
+    }
+}
+
+impl From<validator::ValidationErrors> for ApiError {
+    fn from(e: validator::ValidationErrors) -> Self {
+        let mut fields: Vec<_> = e.field_errors().into_iter().collect();
+        fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let errors = fields
+            .into_iter()
+            .flat_map(|(field, errors)| {
+                errors.iter().map(move |error| FieldError {
+                    field: Some(field.to_string()),
+                    message: error
+                        .message
+                        .as_ref()
+                        .map_or_else(|| error.code.to_string(), |m| m.to_string()),
+                })
+            })
+            .collect();
+        ApiError::Invalid("Request body is invalid".to_owned(), errors)
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status();
+        if let ApiError::Internal(e) = &self {
+            rocket::error!("{}", e);
+        }
+
+        let mut body = json!({
+            "type": "about:blank",
+            "title": status.reason().unwrap_or_default(),
+            "status": status.code,
+            "detail": self.detail(),
+            "code": self.code(),
+            "request_id": request_id(req),
+        });
+        match self {
+            ApiError::Blocked(_, crates) => {
+                body["crates"] = crates
+                    .into_iter()
+                    .map(|c| json!({ "id": c.id, "code": c.code, "name": c.name }))
+                    .collect::<Vec<Value>>()
+                    .into();
+            }
+            ApiError::Duplicate(_, id) => body["existing_id"] = json!(id),
+            ApiError::Invalid(_, errors) => body["errors"] = json!(errors),
+            _ => {}
+        }
+
+        Response::build_from(body.to_string().respond_to(req)?)
+            .status(status)
+            .header(ContentType::new("application", "problem+json"))
+            .ok()
+    }
+}
+
+struct CachedRequestId(String);
+
+// The id of the current request, taken from `X-Request-Id` when the client
+// sends a sane one.
+pub fn request_id<'r>(req: &'r Request<'_>) -> &'r str {
+    &req.local_cache(|| {
+        let id = req
+            .headers()
+            .get_one("X-Request-Id")
+            .filter(|v| {
+                !v.is_empty()
+                    && v.len() <= 64
+                    && v.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            })
+            .map(|v| v.to_owned())
+            .unwrap_or_else(|| {
+                rand::thread_rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(16)
+                    .map(char::from)
+                    .collect()
+            });
+        CachedRequestId(id)
+    })
+    .0
+}
+
+pub struct RequestId;
+
+#[rocket::async_trait]
+impl Fairing for RequestId {
+    fn info(&self) -> Info {
+        Info {
+            name: "Tag requests and responses with a request id",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _data: &mut Data<'_>) {
+        request_id(req);
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        res.set_raw_header("X-Request-Id", request_id(req).to_owned());
+    }
+}
+
+// Set by `JsonBody` so the catchers can report why a request body was rejected.
+pub(crate) struct BodyError(pub Option<ApiError>);
+
+fn body_error(req: &Request<'_>) -> Option<ApiError> {
+    req.local_cache(|| BodyError(None)).0.clone()
+}
+
+#[rocket::catch(400)]
+pub fn bad_request(req: &Request) -> ApiError {
+    body_error(req).unwrap_or_else(|| ApiError::BadRequest("Bad request".to_owned()))
+}
+
+#[rocket::catch(401)]
+pub fn unauthorized() -> ApiError {
+    ApiError::Unauthorized("Missing or invalid credentials".to_owned())
+}
+
+#[rocket::catch(403)]
+pub fn forbidden() -> ApiError {
+    ApiError::Forbidden("Insufficient permissions".to_owned())
+}
+
+#[rocket::catch(404)]
+pub fn not_found() -> ApiError {
+    ApiError::not_found()
+}
+
+#[rocket::catch(422)]
+pub fn unprocessable_entity(req: &Request) -> ApiError {
+    body_error(req).unwrap_or_else(|| ApiError::Validation("Unprocessable entity".to_owned()))
+}
+
+#[rocket::catch(503)]
+pub fn service_unavailable() -> ApiError {
+    ApiError::Unavailable("Service temporarily unavailable".to_owned())
+}
+
+#[rocket::catch(500)]
+pub fn internal_error() -> ApiError {
+    ApiError::Internal("Unhandled error".to_owned())
+}
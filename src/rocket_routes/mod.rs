@@ -1,106 +1,621 @@
-use std::error::Error;
+use std::{marker::PhantomData, ops::Deref};
 
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
 use rocket::{
+    data::{self, Data, FromData, Limits},
     fairing::{Fairing, Info, Kind},
-    http::Status,
+    http::{Method, RawStr, Status},
     request::{FromRequest, Outcome},
-    response::status::Custom,
+    response::{self, Responder},
     Request, Response,
 };
-use rocket_db_pools::Connection;
+use rocket_db_pools::{Connection, Database};
 
-use rocket_db_pools::deadpool_redis::redis::AsyncCommands;
+use serde::{de::DeserializeOwned, Serialize};
 use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
 
 use crate::{
-    models::{RoleCode, User},
-    repositories::{RoleRepository, UserRepository},
+    auth::{hash_token, API_TOKEN_PREFIX},
+    models::{ApiScope, ApiToken, Cursor, CursorPage, Page, RoleCode, User},
+    repositories::{ApiTokenRepository, RoleRepository, SessionRepository, UserRepository},
 };
 
 pub mod authorization;
 pub mod crates;
+mod error;
 pub mod rustaceans;
 
+use error::BodyError;
+pub use error::{
+    bad_request, forbidden, internal_error, not_found, request_id, service_unavailable,
+    unauthorized, unprocessable_entity, ApiError, FieldError, RequestId,
+};
+
 #[derive(rocket_db_pools::Database)]
 #[database("postgres")]
 pub struct DbConn(rocket_db_pools::diesel::PgPool);
 
 #[derive(rocket_db_pools::Database)]
 #[database("redis")]
-
 pub struct CacheConn(rocket_db_pools::deadpool_redis::Pool);
 
-pub fn server_error(e: Box<dyn Error>) -> Custom<Value> {
-    rocket::error!("{}", e);
-    return Custom(Status::InternalServerError, json!("Error"));
+pub fn parse_timestamp(value: &str) -> Result<NaiveDateTime, ApiError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|dt| dt.naive_utc())
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f"))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map(|d| d.and_hms_opt(0, 0, 0).unwrap_or_default())
+        })
+        .map_err(|_| ApiError::Validation(format!("Invalid timestamp: {}", value)))
+}
+
+fn link(path: &str, params: &[(&str, Option<String>)]) -> String {
+    let query = params
+        .iter()
+        .filter_map(|(name, value)| {
+            value
+                .as_ref()
+                .map(|value| format!("{}={}", name, RawStr::new(value).percent_encode()))
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    format!("{}?{}", path, query)
+}
+
+pub fn page_response<T: Serialize>(
+    path: &str,
+    page: Page<T>,
+    params: Vec<(&str, Option<String>)>,
+) -> Value {
+    let link_to = |page_no: i64| {
+        let mut query = vec![
+            ("page", Some(page_no.to_string())),
+            ("per_page", Some(page.per_page.to_string())),
+        ];
+        query.extend(params.iter().cloned());
+        link(path, &query)
+    };
+    let next = page.has_next().then(|| link_to(page.page + 1));
+    let prev = page.has_prev().then(|| link_to(page.page - 1));
+
+    json!({
+        "items": page.items,
+        "total": page.total,
+        "page": page.page,
+        "per_page": page.per_page,
+        "links": {
+            "next": next,
+            "prev": prev,
+        }
+    })
+}
+
+pub fn cursor_response<T: Serialize>(
+    path: &str,
+    page: CursorPage<T>,
+    params: Vec<(&str, Option<String>)>,
+) -> Value {
+    let next = page.next_cursor.as_ref().map(|cursor| {
+        let mut query = vec![
+            ("cursor", Some(cursor.clone())),
+            ("per_page", Some(page.per_page.to_string())),
+        ];
+        query.extend(params.iter().cloned());
+        link(path, &query)
+    });
+
+    json!({
+        "items": page.items,
+        "per_page": page.per_page,
+        "next_cursor": page.next_cursor,
+        "links": {
+            "next": next,
+        }
+    })
+}
+
+pub fn parse_cursor(token: &str) -> Result<Option<Cursor>, ApiError> {
+    if token.is_empty() {
+        return Ok(None);
+    }
+    Cursor::decode(token)
+        .map(Some)
+        .ok_or_else(|| ApiError::Validation("Invalid cursor".to_owned()))
+}
+
+// A JSON request body. Unlike `Json<T>`, a body that fails to parse is reported
+// with the offending field, which the 400 and 422 catchers pick up.
+pub struct JsonBody<T>(pub T);
+
+impl<T> JsonBody<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for JsonBody<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+fn invalid_body(e: serde_path_to_error::Error<serde_json::Error>) -> ApiError {
+    let path = e.path().to_string();
+    let inner = e.into_inner();
+    if inner.classify() != serde_json::error::Category::Data {
+        return ApiError::BadRequest(format!("Malformed JSON: {}", inner));
+    }
+
+    let message = inner.to_string();
+    let message = message
+        .rsplit_once(" at line ")
+        .map_or(message.as_str(), |(m, _)| m)
+        .to_owned();
+    // serde reports a missing field against the object that lacks it.
+    let missing = message
+        .strip_prefix("missing field `")
+        .and_then(|rest| rest.strip_suffix('`'));
+    let field = match (path.as_str(), missing) {
+        (".", Some(name)) => Some(name.to_owned()),
+        (".", None) => None,
+        (_, Some(name)) => Some(format!("{}.{}", path, name)),
+        (_, None) => Some(path),
+    };
+    ApiError::Invalid(
+        "Request body is invalid".to_owned(),
+        vec![FieldError { field, message }],
+    )
+}
+
+fn parse_body<T: DeserializeOwned>(body: &str) -> Result<T, ApiError> {
+    let de = &mut serde_json::Deserializer::from_str(body);
+    serde_path_to_error::deserialize(de).map_err(invalid_body)
+}
+
+// JSON Merge Patch (RFC 7396): objects merge recursively, `null` removes a
+// member and anything else replaces the target.
+fn merge_patch(target: &mut Value, patch: Value) {
+    match patch {
+        Value::Object(members) => {
+            if !target.is_object() {
+                *target = json!({});
+            }
+            let object = target.as_object_mut().unwrap();
+            for (key, value) in members {
+                if value.is_null() {
+                    object.remove(&key);
+                } else {
+                    merge_patch(object.entry(key).or_insert(Value::Null), value);
+                }
+            }
+        }
+        patch => *target = patch,
+    }
+}
+
+// Applies a merge patch to a record, so a cleared `Option` field comes back as
+// `None` and a cleared required field is reported like a missing one.
+pub fn apply_merge_patch<T: Serialize + DeserializeOwned>(
+    record: &T,
+    patch: Value,
+) -> Result<T, ApiError> {
+    let mut value = json!(record);
+    merge_patch(&mut value, patch);
+    serde_path_to_error::deserialize(value).map_err(invalid_body)
 }
 
 #[rocket::async_trait]
-impl<'r> FromRequest<'r> for User {
+impl<'r, T: DeserializeOwned> FromData<'r> for JsonBody<T> {
     type Error = ();
 
-    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let token = req
-            .headers()
-            .get_one("Authorization")
-            .map(|v| v.split_whitespace().collect::<Vec<_>>())
-            .filter(|v| v.len() == 2 && v[0] == "Bearer");
-        if let Some(header_value) = token {
-            let mut cache = req
-                .guard::<Connection<CacheConn>>()
-                .await
-                .expect("Cannot connect to redis in request guard");
-
-            let mut db = req
-                .guard::<Connection<DbConn>>()
-                .await
-                .expect("Cannot connect to redis in request guard");
-
-            let result = cache
-                .get::<String, i32>(format!("sessions/{}", header_value[1]))
-                .await;
-            if let Ok(user_id) = result {
-                if let Ok(user) = UserRepository::find_by_id(&mut db, user_id).await {
-                    return Outcome::Success(user);
-                }
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let limit = req.limits().get("json").unwrap_or(Limits::JSON);
+        let result = match data.open(limit).into_string().await {
+            Ok(body) if body.is_complete() => parse_body(&body),
+            Ok(_) => Err(ApiError::BadRequest(format!(
+                "Request body exceeds {}",
+                limit
+            ))),
+            Err(e) => Err(ApiError::BadRequest(e.to_string())),
+        };
+
+        match result {
+            Ok(value) => data::Outcome::Success(JsonBody(value)),
+            Err(e) => {
+                let status = e.status();
+                req.local_cache(|| BodyError(Some(e)));
+                data::Outcome::Error((status, ()))
+            }
+        }
+    }
+}
+
+// Versions are told apart by `updated_at`, which a trigger bumps on every change.
+pub fn etag(updated_at: NaiveDateTime) -> String {
+    format!("\"{}\"", updated_at.and_utc().timestamp_micros())
+}
+
+// The entity tags listed in a conditional header, if the request sent one.
+fn entity_tags(req: &Request<'_>, name: &str) -> Option<Vec<String>> {
+    req.headers().get(name).fold(None, |tags, value| {
+        let mut tags: Vec<String> = tags.unwrap_or_default();
+        tags.extend(
+            value
+                .split(',')
+                .map(|tag| tag.trim().to_owned())
+                .filter(|tag| !tag.is_empty()),
+        );
+        Some(tags)
+    })
+}
+
+// A response tagged with the version of what it shows. GET requests whose
+// `If-None-Match` or `If-Modified-Since` still match it get a bodiless 304.
+pub struct Versioned<R> {
+    etag: String,
+    last_modified: Option<NaiveDateTime>,
+    inner: R,
+}
+
+impl<R> Versioned<R> {
+    pub fn new(updated_at: NaiveDateTime, inner: R) -> Self {
+        Versioned {
+            etag: etag(updated_at),
+            last_modified: Some(updated_at),
+            inner,
+        }
+    }
+
+    fn not_modified(&self, req: &Request<'_>) -> bool {
+        if !matches!(req.method(), Method::Get | Method::Head) {
+            return false;
+        }
+        // If-None-Match takes precedence and compares tags weakly.
+        if let Some(tags) = entity_tags(req, "If-None-Match") {
+            let current = self.etag.trim_start_matches("W/");
+            return tags
+                .iter()
+                .any(|tag| tag == "*" || tag.trim_start_matches("W/") == current);
+        }
+        match (
+            self.last_modified,
+            req.headers().get_one("If-Modified-Since"),
+        ) {
+            (Some(last_modified), Some(since)) => DateTime::parse_from_rfc2822(since)
+                .is_ok_and(|since| last_modified.and_utc().timestamp() <= since.timestamp()),
+            _ => false,
+        }
+    }
+}
+
+impl Versioned<Value> {
+    // A listing has no version of its own, so it is tagged by its content and
+    // dated by the most recent change to its table.
+    pub fn listing(last_modified: Option<NaiveDateTime>, body: Value) -> Self {
+        let digest = Sha256::digest(body.to_string().as_bytes());
+        Versioned {
+            etag: format!("W/\"{:x}\"", digest),
+            last_modified,
+            inner: body,
+        }
+    }
+}
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for Versioned<R> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'o> {
+        let mut response = if self.not_modified(req) {
+            Response::build().status(Status::NotModified).finalize()
+        } else {
+            self.inner.respond_to(req)?
+        };
+        response.set_raw_header("ETag", self.etag);
+        response.set_raw_header("Cache-Control", "private, no-cache");
+        if let Some(last_modified) = self.last_modified {
+            response.set_raw_header(
+                "Last-Modified",
+                last_modified
+                    .and_utc()
+                    .format("%a, %d %b %Y %H:%M:%S GMT")
+                    .to_string(),
+            );
+        }
+        Ok(response)
+    }
+}
+
+// The entity tags of an `If-Match` header, if the request sent one.
+pub struct IfMatch(Option<Vec<String>>);
+
+impl IfMatch {
+    // The version the request expects the record to be at, given the one it is
+    // at now. Repositories handed that version only update or delete a record
+    // still at it, and report `NotFound` or 0 rows for one that has moved on.
+    pub fn expected(&self, updated_at: NaiveDateTime) -> Result<Option<NaiveDateTime>, ApiError> {
+        let current = etag(updated_at);
+        match &self.0 {
+            None => Ok(None),
+            Some(tags) if tags.iter().any(|tag| tag == "*" || *tag == current) => {
+                Ok(Some(updated_at))
             }
+            Some(_) => Err(ApiError::modified()),
         }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfMatch {
+    type Error = ();
 
-        Outcome::Error((Status::Unauthorized, ()))
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(IfMatch(entity_tags(req, "If-Match")))
     }
 }
 
-pub struct EditorUser(User);
+fn bearer_token<'r>(req: &'r Request<'_>) -> Option<&'r str> {
+    req.headers()
+        .get_one("Authorization")
+        .map(|v| v.split_whitespace().collect::<Vec<_>>())
+        .filter(|v| v.len() == 2 && v[0] == "Bearer")
+        .map(|v| v[1])
+}
+
+pub struct ClientInfo {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
 
 #[rocket::async_trait]
-impl<'r> FromRequest<'r> for EditorUser {
+impl<'r> FromRequest<'r> for ClientInfo {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(ClientInfo {
+            ip: req.client_ip().map(|ip| ip.to_string()),
+            user_agent: req.headers().get_one("User-Agent").map(str::to_owned),
+        })
+    }
+}
+
+pub struct SessionToken(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SessionToken {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match bearer_token(req) {
+            Some(token) => Outcome::Success(SessionToken(token.to_owned())),
+            None => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+// Pools that cannot hand out a connection fail the guard with 503 instead of
+// panicking.
+async fn connection<D: Database>(req: &Request<'_>) -> Result<Connection<D>, Status> {
+    req.guard::<Connection<D>>()
+        .await
+        .succeeded()
+        .ok_or(Status::ServiceUnavailable)
+}
+
+// Who the bearer token of a request belongs to. Resolving it marks the session
+// or API token as used, so it is worked out once per request however many
+// guards ask.
+struct Principal {
+    user: User,
+    api_token: Option<ApiToken>,
+}
+
+impl Principal {
+    // Sessions may use any route, personal API tokens only routes declaring a
+    // scope they were granted.
+    fn user_for(&self, scope: Option<ApiScope>) -> Result<User, Status> {
+        match (&self.api_token, scope) {
+            (None, _) => Ok(self.user.clone()),
+            (Some(api_token), Some(scope)) if api_token.allows(scope) => Ok(self.user.clone()),
+            (Some(_), _) => Err(Status::Forbidden),
+        }
+    }
+}
+
+async fn api_token_principal(req: &Request<'_>, token: &str) -> Result<Principal, Status> {
+    let mut db = connection::<DbConn>(req).await?;
+    let api_token = ApiTokenRepository::use_token(&mut db, &hash_token(token))
+        .await
+        .map_err(|_| Status::Unauthorized)?;
+    let user = UserRepository::find_by_id(&mut db, api_token.user_id)
+        .await
+        .map_err(|_| Status::Unauthorized)?;
+    Ok(Principal {
+        user,
+        api_token: Some(api_token),
+    })
+}
+
+async fn session_principal(req: &Request<'_>, token: &str) -> Result<Principal, Status> {
+    let mut cache = connection::<CacheConn>(req).await?;
+    let mut db = connection::<DbConn>(req).await?;
+    let Ok(Some(user_id)) = SessionRepository::touch(&mut cache, token).await else {
+        return Err(Status::Unauthorized);
+    };
+    let user = UserRepository::find_by_id(&mut db, user_id)
+        .await
+        .map_err(|_| Status::Unauthorized)?;
+    Ok(Principal {
+        user,
+        api_token: None,
+    })
+}
+
+async fn authenticate(req: &Request<'_>, scope: Option<ApiScope>) -> Outcome<User, ()> {
+    let principal = req
+        .local_cache_async(async {
+            match bearer_token(req) {
+                Some(token) if token.starts_with(API_TOKEN_PREFIX) => {
+                    api_token_principal(req, token).await
+                }
+                Some(token) => session_principal(req, token).await,
+                None => Err(Status::Unauthorized),
+            }
+        })
+        .await;
+    match principal
+        .as_ref()
+        .map_err(|&status| status)
+        .and_then(|principal| principal.user_for(scope))
+    {
+        Ok(user) => Outcome::Success(user),
+        Err(status) => Outcome::Error((status, ())),
+    }
+}
+
+// Guards that narrow down the authenticated user, e.g. by role. Wrapped in
+// `Scoped`, they also admit personal API tokens.
+#[rocket::async_trait]
+pub trait FromUser: Sized {
+    async fn from_user(req: &Request<'_>, user: User) -> Outcome<Self, ()>;
+}
+
+#[rocket::async_trait]
+impl FromUser for User {
+    async fn from_user(_req: &Request<'_>, user: User) -> Outcome<Self, ()> {
+        Outcome::Success(user)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for User {
     type Error = ();
 
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let user = req
-            .guard::<User>()
-            .await
-            .expect("Cannot retrive current logged in user");
+        authenticate(req, None).await
+    }
+}
+
+pub struct EditorUser(pub User);
 
-        let mut db = req
-            .guard::<Connection<DbConn>>()
-            .await
-            .expect("Cannot connect to redis in request guard");
+#[rocket::async_trait]
+impl FromUser for EditorUser {
+    async fn from_user(req: &Request<'_>, user: User) -> Outcome<Self, ()> {
+        let mut db = match connection::<DbConn>(req).await {
+            Ok(db) => db,
+            Err(status) => return Outcome::Error((status, ())),
+        };
 
         if let Ok(roles) = RoleRepository::find_by_user(&mut db, &user).await {
             rocket::info!("Roles assign are, {:?}", roles);
-            let is_editor = roles.iter().any(|role| match role.code {
-                RoleCode::Admin => true,
-                RoleCode::Editor => true,
-                _ => false,
-            });
+            let is_editor = roles
+                .iter()
+                .any(|role| matches!(role.code, RoleCode::Admin | RoleCode::Editor));
             rocket::info!("Is Editor is, {}", is_editor);
             if is_editor {
                 return Outcome::Success(EditorUser(user));
             }
         }
 
-        Outcome::Error((Status::Unauthorized, ()))
+        Outcome::Error((Status::Forbidden, ()))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for EditorUser {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user = rocket::outcome::try_outcome!(authenticate(req, None).await);
+        EditorUser::from_user(req, user).await
+    }
+}
+
+pub struct AdminUser(pub User);
+
+#[rocket::async_trait]
+impl FromUser for AdminUser {
+    async fn from_user(req: &Request<'_>, user: User) -> Outcome<Self, ()> {
+        let mut db = match connection::<DbConn>(req).await {
+            Ok(db) => db,
+            Err(status) => return Outcome::Error((status, ())),
+        };
+
+        if let Ok(roles) = RoleRepository::find_by_user(&mut db, &user).await {
+            if roles
+                .iter()
+                .any(|role| matches!(role.code, RoleCode::Admin))
+            {
+                return Outcome::Success(AdminUser(user));
+            }
+        }
+
+        Outcome::Error((Status::Forbidden, ()))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminUser {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user = rocket::outcome::try_outcome!(authenticate(req, None).await);
+        AdminUser::from_user(req, user).await
+    }
+}
+
+// The scope a personal API token needs to pass a `Scoped` guard.
+pub trait Scope: Send + Sync + 'static {
+    const SCOPE: ApiScope;
+}
+
+pub struct CratesRead;
+pub struct CratesWrite;
+pub struct RustaceansRead;
+pub struct RustaceansWrite;
+
+impl Scope for CratesRead {
+    const SCOPE: ApiScope = ApiScope::CratesRead;
+}
+
+impl Scope for CratesWrite {
+    const SCOPE: ApiScope = ApiScope::CratesWrite;
+}
+
+impl Scope for RustaceansRead {
+    const SCOPE: ApiScope = ApiScope::RustaceansRead;
+}
+
+impl Scope for RustaceansWrite {
+    const SCOPE: ApiScope = ApiScope::RustaceansWrite;
+}
+
+// The guard `G`, which personal API tokens granted the scope `S` pass as well.
+// Without it, routes only take session tokens.
+pub struct Scoped<S, G = User> {
+    guard: G,
+    scope: PhantomData<S>,
+}
+
+impl<S, G> Deref for Scoped<S, G> {
+    type Target = G;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, S: Scope, G: FromUser + Send> FromRequest<'r> for Scoped<S, G> {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user = rocket::outcome::try_outcome!(authenticate(req, Some(S::SCOPE)).await);
+        G::from_user(req, user).await.map(|guard| Scoped {
+            guard,
+            scope: PhantomData,
+        })
     }
 }
 
@@ -122,8 +637,12 @@ impl Fairing for Cors {
 
     async fn on_response<'r>(&self, _req: &'r Request<'_>, res: &mut Response<'r>) {
         res.set_raw_header("Access-Control-Allow-Origin", "*");
-        res.set_raw_header("Access-Control-Allow-Methods", "GET, POST, PUT, DELETE");
+        res.set_raw_header(
+            "Access-Control-Allow-Methods",
+            "GET, POST, PUT, PATCH, DELETE",
+        );
         res.set_raw_header("Access-Control-Allow-Headers", "*");
+        res.set_raw_header("Access-Control-Expose-Headers", "ETag, X-Request-Id");
         res.set_raw_header("Access-Control-Allow-Credentials", "true");
     }
 }
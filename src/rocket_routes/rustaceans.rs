@@ -1,81 +1,272 @@
-use crate::models::User;
-use crate::rocket_routes::{server_error, DbConn, EditorUser};
+use crate::rocket_routes::{
+    apply_merge_patch, cursor_response, page_response, parse_cursor, parse_timestamp, AdminUser,
+    ApiError, DbConn, EditorUser, IfMatch, JsonBody, RustaceansRead, RustaceansWrite, Scoped,
+    Versioned,
+};
 use crate::{
-    models::{NewRustacean, Rustacean},
-    repositories::RustaceanRepository,
+    models::{
+        CrateFilter, NewRustacean, PageRequest, Rustacean, RustaceanFilter, Sort, SortField,
+        SortOrder, User,
+    },
+    repositories::{CrateRepository, RustaceanRepository},
 };
+use chrono::NaiveDateTime;
 use rocket::{
     http::Status,
     response::status::{Custom, NoContent},
-    serde::json::{json, Json, Value},
+    serde::json::{json, Value},
 };
 use rocket_db_pools::Connection;
+use validator::Validate;
+
+async fn email_taken(db: &mut Connection<DbConn>, email: &str) -> ApiError {
+    match RustaceanRepository::find_by_email(db, email).await {
+        Ok(existing) => ApiError::Duplicate("Email is already taken".to_owned(), existing.id),
+        Err(e) => e.into(),
+    }
+}
+
+#[derive(rocket::FromForm)]
+pub struct RustaceanListQuery {
+    page: Option<i64>,
+    per_page: Option<i64>,
+    sort: Option<SortField>,
+    order: Option<SortOrder>,
+    created_after: Option<String>,
+    cursor: Option<String>,
+}
 
-#[rocket::get("/rustaceans")]
+#[rocket::get("/rustaceans?<query..>")]
 pub async fn get_rustaceans(
     mut db: Connection<DbConn>,
-    _user: User,
-) -> Result<Value, Custom<Value>> {
-    RustaceanRepository::find_all(&mut db, 100)
+    query: RustaceanListQuery,
+    _user: Scoped<RustaceansRead>,
+) -> Result<Versioned<Value>, ApiError> {
+    let filter = RustaceanFilter {
+        created_after: query
+            .created_after
+            .as_deref()
+            .map(parse_timestamp)
+            .transpose()?,
+    };
+    let params = vec![
+        ("sort", query.sort.map(|v| v.as_str().to_owned())),
+        ("order", query.order.map(|v| v.as_str().to_owned())),
+        ("created_after", query.created_after.clone()),
+    ];
+
+    let last_modified = RustaceanRepository::last_modified(&mut db).await?;
+
+    if let Some(cursor) = query.cursor.as_deref() {
+        if matches!(query.sort, Some(SortField::Name)) {
+            return Err(ApiError::Validation(
+                "Cursor pagination only supports sort=created_at".to_owned(),
+            ));
+        }
+        let cursor = parse_cursor(cursor)?;
+        let per_page = PageRequest::new(None, query.per_page).per_page;
+
+        return RustaceanRepository::find_after(
+            &mut db,
+            &filter,
+            query.order.unwrap_or_default(),
+            cursor,
+            per_page,
+        )
+        .await
+        .map(|r| Versioned::listing(last_modified, cursor_response("/rustaceans", r, params)))
+        .map_err(ApiError::from);
+    }
+
+    let sort = Sort {
+        field: query.sort.unwrap_or_default(),
+        order: query.order.unwrap_or_default(),
+    };
+    let page = PageRequest::new(query.page, query.per_page);
+
+    RustaceanRepository::find_page(&mut db, &filter, sort, page)
+        .await
+        .map(|r| Versioned::listing(last_modified, page_response("/rustaceans", r, params)))
+        .map_err(ApiError::from)
+}
+
+#[rocket::get("/rustaceans/autocomplete?<prefix>&<limit>")]
+pub async fn autocomplete_rustaceans(
+    mut db: Connection<DbConn>,
+    prefix: &str,
+    limit: Option<i64>,
+    _user: Scoped<RustaceansRead>,
+) -> Result<Value, ApiError> {
+    let prefix = prefix.trim();
+    if prefix.is_empty() {
+        return Err(ApiError::Validation("Prefix must not be empty".to_owned()));
+    }
+
+    RustaceanRepository::autocomplete(&mut db, prefix, limit.unwrap_or(10).clamp(1, 50))
         .await
         .map(|r| json!(r))
-        .map_err(|e| server_error(e.into()))
+        .map_err(ApiError::from)
 }
 
 #[rocket::get("/rustaceans/<id>")]
 pub async fn get_rustacean(
     mut db: Connection<DbConn>,
     id: i32,
-    _user: User,
-) -> Result<Value, Custom<Value>> {
+    _user: Scoped<RustaceansRead>,
+) -> Result<Versioned<Value>, ApiError> {
     RustaceanRepository::find(&mut db, id)
         .await
-        .map(|r| json!(r))
-        .map_err(|e| match e {
-            diesel::result::Error::NotFound => Custom(Status::NotFound, json!("Not found")),
-            _ => server_error(e.into()),
-        })
+        .map(|r| Versioned::new(r.updated_at, json!(r)))
+        .map_err(ApiError::from)
 }
 
 #[rocket::post("/rustaceans", format = "json", data = "<new_rustacean>")]
 pub async fn crate_rustacean(
     mut db: Connection<DbConn>,
-    new_rustacean: Json<NewRustacean>,
-    _user: EditorUser,
-) -> Result<Custom<Value>, Custom<Value>> {
-    RustaceanRepository::create(&mut db, new_rustacean.into_inner())
-        .await
-        .map(|r| Custom(Status::Created, json!(r)))
-        .map_err(|e| server_error(e.into()))
+    new_rustacean: JsonBody<NewRustacean>,
+    user: Scoped<RustaceansWrite, EditorUser>,
+) -> Result<Custom<Value>, ApiError> {
+    new_rustacean.validate()?;
+    let email = new_rustacean.email.clone();
+
+    match RustaceanRepository::create(&mut db, new_rustacean.into_inner(), &user.0).await {
+        Ok(r) => Ok(Custom(Status::Created, json!(r))),
+        Err(diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::UniqueViolation,
+            _,
+        )) => Err(email_taken(&mut db, &email).await),
+        Err(e) => Err(e.into()),
+    }
 }
 
 #[rocket::put("/rustaceans/<id>", format = "json", data = "<rustacean>")]
 pub async fn update_rustacean(
     mut db: Connection<DbConn>,
     id: i32,
-    rustacean: Json<Rustacean>,
-    _user: EditorUser,
-) -> Result<Value, Custom<Value>> {
-    RustaceanRepository::update(&mut db, id, rustacean.into_inner())
-        .await
-        .map(|r| json!(r))
-        .map_err(|e| match e {
-            diesel::result::Error::NotFound => Custom(Status::NotFound, json!("Not found")),
-            _ => server_error(e.into()),
-        })
+    rustacean: JsonBody<Rustacean>,
+    if_match: IfMatch,
+    user: Scoped<RustaceansWrite, EditorUser>,
+) -> Result<Versioned<Value>, ApiError> {
+    rustacean.validate()?;
+    let updated_at = if_match.expected(RustaceanRepository::find(&mut db, id).await?.updated_at)?;
+
+    match save_rustacean(&mut db, id, rustacean.into_inner(), updated_at, &user.0).await? {
+        Some(r) => Ok(Versioned::new(r.updated_at, json!(r))),
+        None => Err(ApiError::modified()),
+    }
+}
+
+// `None` when the rustacean is no longer at `updated_at`.
+async fn save_rustacean(
+    db: &mut Connection<DbConn>,
+    id: i32,
+    rustacean: Rustacean,
+    updated_at: Option<NaiveDateTime>,
+    user: &User,
+) -> Result<Option<Rustacean>, ApiError> {
+    let email = rustacean.email.clone();
+
+    match RustaceanRepository::update(db, id, rustacean, updated_at, user).await {
+        Ok(r) => Ok(Some(r)),
+        Err(diesel::result::Error::NotFound) if updated_at.is_some() => Ok(None),
+        Err(diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::UniqueViolation,
+            _,
+        )) => Err(email_taken(db, &email).await),
+        Err(e) => Err(e.into()),
+    }
 }
 
-#[rocket::delete("/rustaceans/<id>")]
+// Like `patch_crate`, the patch is only written over the version it was
+// applied to and reapplied when the rustacean changed without `If-Match`.
+#[rocket::patch("/rustaceans/<id>", data = "<patch>")]
+pub async fn patch_rustacean(
+    mut db: Connection<DbConn>,
+    id: i32,
+    patch: JsonBody<Value>,
+    if_match: IfMatch,
+    user: Scoped<RustaceansWrite, EditorUser>,
+) -> Result<Versioned<Value>, ApiError> {
+    let patch = patch.into_inner();
+    let mut current = RustaceanRepository::find(&mut db, id).await?;
+    let conditional = if_match.expected(current.updated_at)?.is_some();
+
+    loop {
+        let rustacean: Rustacean = apply_merge_patch(&current, patch.clone())?;
+        rustacean.validate()?;
+
+        match save_rustacean(&mut db, id, rustacean, Some(current.updated_at), &user.0).await? {
+            Some(r) => return Ok(Versioned::new(r.updated_at, json!(r))),
+            None if conditional => return Err(ApiError::modified()),
+            None => current = RustaceanRepository::find(&mut db, id).await?,
+        }
+    }
+}
+
+// A code taken while the rustacean was deleted can keep one of its crates from
+// coming back, in which case nothing is restored.
+#[rocket::post("/rustaceans/<id>/restore")]
+pub async fn restore_rustacean(
+    mut db: Connection<DbConn>,
+    id: i32,
+    admin: Scoped<RustaceansWrite, AdminUser>,
+) -> Result<Versioned<Value>, ApiError> {
+    let rustacean = RustaceanRepository::find_deleted(&mut db, id).await?;
+
+    match RustaceanRepository::restore(&mut db, id, &admin.0).await {
+        Ok(r) => Ok(Versioned::new(r.updated_at, json!(r))),
+        Err(diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::UniqueViolation,
+            _,
+        )) => match RustaceanRepository::find_by_email(&mut db, &rustacean.email).await {
+            Ok(existing) => Err(ApiError::Duplicate(
+                "Email is already taken".to_owned(),
+                existing.id,
+            )),
+            Err(diesel::result::Error::NotFound) => Err(ApiError::Conflict(
+                "A crate code of this rustacean is already taken".to_owned(),
+            )),
+            Err(e) => Err(e.into()),
+        },
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[rocket::delete("/rustaceans/<id>?<cascade>")]
 pub async fn delete_rustaceans(
     mut db: Connection<DbConn>,
     id: i32,
-    _user: EditorUser,
-) -> Result<NoContent, Custom<Value>> {
-    RustaceanRepository::delete(&mut db, id)
-        .await
-        .map(|_| NoContent)
-        .map_err(|e| match e {
-            diesel::result::Error::NotFound => Custom(Status::NotFound, json!("Not found")),
-            _ => server_error(e.into()),
-        })
+    cascade: Option<bool>,
+    if_match: IfMatch,
+    user: Scoped<RustaceansWrite, EditorUser>,
+    admin: Option<Scoped<RustaceansWrite, AdminUser>>,
+) -> Result<NoContent, ApiError> {
+    let cascade = cascade.unwrap_or(false);
+    if cascade && admin.is_none() {
+        return Err(ApiError::Forbidden(
+            "Only admins can cascade deletes".to_owned(),
+        ));
+    }
+    let updated_at = if_match.expected(RustaceanRepository::find(&mut db, id).await?.updated_at)?;
+    if !cascade {
+        let filter = CrateFilter {
+            rustacean_id: Some(id),
+            ..Default::default()
+        };
+        let crates = CrateRepository::find_all(&mut db, &filter).await?;
+        if !crates.is_empty() {
+            return Err(ApiError::Blocked(
+                "Rustacean still owns crates".to_owned(),
+                crates,
+            ));
+        }
+    }
+
+    if RustaceanRepository::delete(&mut db, id, cascade, updated_at, &user.0).await? == 0 {
+        return Err(match updated_at {
+            Some(_) => ApiError::modified(),
+            None => ApiError::not_found(),
+        });
+    }
+    Ok(NoContent)
 }
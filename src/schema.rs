@@ -1,5 +1,47 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 64]
+        name -> Varchar,
+        #[max_length = 64]
+        token_hash -> Varchar,
+        #[max_length = 255]
+        scopes -> Varchar,
+        expires_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    crate_dependencies (id) {
+        id -> Int4,
+        crate_version_id -> Int4,
+        dependency_id -> Int4,
+        #[max_length = 64]
+        req -> Varchar,
+        #[max_length = 16]
+        kind -> Varchar,
+        optional -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    crate_versions (id) {
+        id -> Int4,
+        crate_id -> Int4,
+        #[max_length = 64]
+        version -> Varchar,
+        description -> Nullable<Text>,
+        yanked -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     crates (id) {
         id -> Int4,
@@ -12,6 +54,23 @@ diesel::table! {
         version -> Varchar,
         description -> Nullable<Text>,
         created_at -> Timestamp,
+        updated_at -> Timestamp,
+        deleted_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    history (id) {
+        id -> Int4,
+        #[max_length = 64]
+        record_table -> Varchar,
+        record_id -> Int4,
+        #[max_length = 16]
+        action -> Varchar,
+        user_id -> Nullable<Int4>,
+        before -> Nullable<Jsonb>,
+        after -> Nullable<Jsonb>,
+        created_at -> Timestamp,
     }
 }
 
@@ -32,6 +91,8 @@ diesel::table! {
         name -> Varchar,
         email -> Varchar,
         created_at -> Timestamp,
+        updated_at -> Timestamp,
+        deleted_at -> Nullable<Timestamp>,
     }
 }
 
@@ -54,12 +115,21 @@ diesel::table! {
     }
 }
 
+diesel::joinable!(api_tokens -> users (user_id));
+diesel::joinable!(crate_dependencies -> crate_versions (crate_version_id));
+diesel::joinable!(crate_dependencies -> crates (dependency_id));
+diesel::joinable!(crate_versions -> crates (crate_id));
 diesel::joinable!(crates -> rustaceans (rustacean_id));
+diesel::joinable!(history -> users (user_id));
 diesel::joinable!(users_roles -> roles (role_id));
 diesel::joinable!(users_roles -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    api_tokens,
+    crate_dependencies,
+    crate_versions,
     crates,
+    history,
     roles,
     rustaceans,
     users,
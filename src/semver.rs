@@ -0,0 +1,19 @@
+// Versions and requirements are parsed by the `semver` crate, which follows
+// Cargo's rules.
+//
+// Versions recorded before SemVer was enforced, such as `0.1`, are kept as they
+// are. They sort below every valid version and never satisfy a requirement.
+use std::cmp::Ordering;
+
+pub use ::semver::{BuildMetadata, Error as SemVerError, Prerelease, Version, VersionReq};
+
+// Orders raw version strings by SemVer precedence, so build metadata is
+// ignored.
+pub fn compare(a: &str, b: &str) -> Ordering {
+    match (a.parse::<Version>(), b.parse::<Version>()) {
+        (Ok(a), Ok(b)) => a.cmp_precedence(&b),
+        (Ok(_), Err(_)) => Ordering::Greater,
+        (Err(_), Ok(_)) => Ordering::Less,
+        (Err(_), Err(_)) => a.cmp(b),
+    }
+}
@@ -1,3 +1,4 @@
+use cr8s::auth::hash_token;
 use reqwest::{blocking::Client, StatusCode};
 use serde_json::{json, Value};
 use std::process::Command;
@@ -34,6 +35,7 @@ fn test_login() {
     let json: Value = response.json().unwrap();
     assert!(json.get("token").is_some());
     assert_eq!(json["token"].as_str().unwrap().len(), 128);
+    assert_eq!(json["refresh_token"].as_str().unwrap().len(), 128);
 
     let response = client
         .post(format!("{}/login", common::APP_HOST))
@@ -47,6 +49,23 @@ fn test_login() {
     assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
 }
 
+#[test]
+fn test_roles_of_new_user() {
+    //SETUP
+    // Every role exists before the new user gets one, so the id of its
+    // users_roles row is not also the id of its role.
+    common::get_client_with_logged_in_admin();
+    common::get_logged_in_client("test_editor", "editor");
+    common::get_client_with_logged_in_viewer();
+    let client = common::get_logged_in_client("test_new_editor", "editor");
+
+    //TEST
+    let rustacean = common::create_test_rustacean(&client);
+
+    //CLEANUP
+    common::delete_test_rustacean(&client, rustacean);
+}
+
 #[test]
 fn test_me() {
     let client = common::get_client_with_logged_in_viewer();
@@ -63,3 +82,473 @@ fn test_me() {
     assert!(json.get("password").is_none());
     assert!(json.get("created_at").is_some());
 }
+
+#[test]
+fn test_guard_failures_return_json() {
+    let response = Client::new()
+        .get(format!("{}/crates", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["code"], "unauthorized");
+    assert_eq!(json["status"], 401);
+
+    let client = common::get_client_with_logged_in_viewer();
+    let response = client
+        .post(format!("{}/rustaceans", common::APP_HOST))
+        .json(&json!({ "name": "Foo bar", "email": "foo@bar.com" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["code"], "forbidden");
+
+    let response = client
+        .get(format!("{}/no-such-route", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["code"], "not_found");
+}
+
+#[test]
+fn test_logout() {
+    //SETUP
+    let client = common::get_logged_in_client("test_logout", "viewer");
+    let other_client = common::get_logged_in_client("test_logout", "viewer");
+
+    //TEST
+    let response = client
+        .post(format!("{}/logout", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = client
+        .get(format!("{}/me", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .post(format!("{}/logout", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Other sessions of the same user survive
+    let response = other_client
+        .get(format!("{}/me", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    //CLEANUP
+    let response = other_client
+        .post(format!("{}/logout", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+#[test]
+fn test_logout_all() {
+    //SETUP
+    let client = common::get_logged_in_client("test_logout_all", "viewer");
+    let other_client = common::get_logged_in_client("test_logout_all", "viewer");
+
+    //TEST
+    let response = client
+        .post(format!("{}/logout-all", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    for client in [client, other_client] {
+        let response = client
+            .get(format!("{}/me", common::APP_HOST))
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
+
+#[test]
+fn test_delete_user_sessions() {
+    //SETUP
+    let client = common::get_logged_in_client("test_revoked", "viewer");
+    let response = client
+        .get(format!("{}/me", common::APP_HOST))
+        .send()
+        .unwrap();
+    let user: Value = response.json().unwrap();
+
+    //TEST
+    let response = client
+        .delete(format!(
+            "{}/users/{}/sessions",
+            common::APP_HOST,
+            user["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let admin_client = common::get_client_with_logged_in_admin();
+    let response = admin_client
+        .delete(format!(
+            "{}/users/{}/sessions",
+            common::APP_HOST,
+            user["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = client
+        .get(format!("{}/me", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = admin_client
+        .delete(format!("{}/users/{}/sessions", common::APP_HOST, 999999))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_my_sessions() {
+    //SETUP
+    let response = common::get_logged_in_client("test_sessions", "viewer")
+        .post(format!("{}/logout-all", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let client = common::get_logged_in_client("test_sessions", "viewer");
+    let response = Client::new()
+        .post(format!("{}/login", common::APP_HOST))
+        .header("User-Agent", "cr8s-test/1.0")
+        .json(&json!({
+            "username":"test_sessions",
+            "password":"1234"
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    let token = json["token"].as_str().unwrap().to_owned();
+
+    //TEST
+    let response = client
+        .get(format!("{}/me/sessions", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.text().unwrap();
+    assert!(!body.contains(&token));
+    let sessions: Value = serde_json::from_str(&body).unwrap();
+    let sessions = sessions.as_array().unwrap();
+    assert_eq!(sessions.len(), 2);
+    assert!(sessions.iter().all(|s| s["ip"] == "127.0.0.1"));
+    assert!(sessions.iter().all(|s| s.get("created_at").is_some()));
+    assert!(sessions.iter().all(|s| s.get("last_seen_at").is_some()));
+    assert!(sessions.iter().all(|s| s.get("expires_at").is_some()));
+    assert_eq!(sessions.iter().filter(|s| s["current"] == true).count(), 1);
+    let other = sessions.iter().find(|s| s["current"] == false).unwrap();
+    assert_eq!(other["user_agent"], "cr8s-test/1.0");
+
+    let admin_client = common::get_client_with_logged_in_admin();
+    let response = admin_client
+        .get(format!(
+            "{}/users/{}/sessions",
+            common::APP_HOST,
+            other["user_id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json.as_array().unwrap().len(), 2);
+
+    let response = client
+        .delete(format!(
+            "{}/me/sessions/{}",
+            common::APP_HOST,
+            other["id"].as_str().unwrap()
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = Client::new()
+        .get(format!("{}/me", common::APP_HOST))
+        .bearer_auth(&token)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .delete(format!(
+            "{}/me/sessions/{}",
+            common::APP_HOST,
+            other["id"].as_str().unwrap()
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    //CLEANUP
+    let response = client
+        .post(format!("{}/logout", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+#[test]
+fn test_refresh_token() {
+    //SETUP
+    Command::new("cargo")
+        .arg("run")
+        .arg("--bin")
+        .arg("cli")
+        .arg("users")
+        .arg("create")
+        .arg("test_refresh")
+        .arg("1234")
+        .arg("viewer")
+        .output()
+        .unwrap();
+    let client = Client::new();
+    let response = client
+        .post(format!("{}/login", common::APP_HOST))
+        .json(&json!({
+            "username":"test_refresh",
+            "password":"1234"
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let login: Value = response.json().unwrap();
+
+    //TEST
+    let response = client
+        .post(format!("{}/token/refresh", common::APP_HOST))
+        .json(&json!({ "refresh_token": login["refresh_token"] }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let refreshed: Value = response.json().unwrap();
+    assert_ne!(refreshed["token"], login["token"]);
+    assert_ne!(refreshed["refresh_token"], login["refresh_token"]);
+
+    let me = |token: &Value| {
+        client
+            .get(format!("{}/me", common::APP_HOST))
+            .bearer_auth(token.as_str().unwrap())
+            .send()
+            .unwrap()
+            .status()
+    };
+    assert_eq!(me(&login["token"]), StatusCode::UNAUTHORIZED);
+    assert_eq!(me(&refreshed["token"]), StatusCode::OK);
+
+    // Reusing a rotated refresh token revokes the whole session
+    let response = client
+        .post(format!("{}/token/refresh", common::APP_HOST))
+        .json(&json!({ "refresh_token": login["refresh_token"] }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(me(&refreshed["token"]), StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .post(format!("{}/token/refresh", common::APP_HOST))
+        .json(&json!({ "refresh_token": refreshed["refresh_token"] }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .post(format!("{}/token/refresh", common::APP_HOST))
+        .json(&json!({ "refresh_token": "no-such-token" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn test_stored_digest_is_not_a_token() {
+    //SETUP
+    let client = Client::new();
+    common::get_client_with_logged_in_viewer();
+    let response = client
+        .post(format!("{}/login", common::APP_HOST))
+        .json(&json!({
+            "username":"test_viewer",
+            "password":"1234"
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let login: Value = response.json().unwrap();
+    let token = login["token"].as_str().unwrap();
+    let refresh_token = login["refresh_token"].as_str().unwrap();
+
+    //TEST
+    // Whoever can read redis sees the digests, which must not work as tokens.
+    let response = client
+        .get(format!("{}/me", common::APP_HOST))
+        .bearer_auth(hash_token(token))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .post(format!("{}/token/refresh", common::APP_HOST))
+        .json(&json!({ "refresh_token": hash_token(refresh_token) }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .get(format!("{}/me", common::APP_HOST))
+        .bearer_auth(token)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[test]
+fn test_api_tokens() {
+    //SETUP
+    let client = common::get_client_with_logged_in_editor();
+
+    //TEST
+    let response = client
+        .post(format!("{}/me/tokens", common::APP_HOST))
+        .json(&json!({
+            "name": "ci",
+            "scopes": ["crates:read", "rustaceans:write", "crates:read"],
+            "expires_at": "2999-01-01T00:00:00"
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let created: Value = response.json().unwrap();
+    let token = created["token"].as_str().unwrap().to_owned();
+    assert!(token.starts_with("cr8s_"));
+    assert_eq!(created["name"], "ci");
+    assert_eq!(
+        created["scopes"],
+        json!(["crates:read", "rustaceans:write"])
+    );
+    assert!(created.get("token_hash").is_none());
+    assert!(created["last_used_at"].is_null());
+
+    let response = client
+        .get(format!("{}/me/tokens", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let tokens: Value = response.json().unwrap();
+    let listed = tokens
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|t| t["id"] == created["id"])
+        .unwrap();
+    assert!(listed.get("token").is_none());
+
+    let bot = Client::new();
+    let response = bot
+        .get(format!("{}/crates", common::APP_HOST))
+        .bearer_auth(&token)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = bot
+        .post(format!("{}/rustaceans", common::APP_HOST))
+        .bearer_auth(&token)
+        .json(&json!({
+            "name": "Bot",
+            "email": format!("bot-{}@bar.com", common::unique_suffix())
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let rustacean: Value = response.json().unwrap();
+
+    // Out of scope
+    for response in [
+        bot.get(format!("{}/rustaceans", common::APP_HOST)),
+        bot.post(format!("{}/crates", common::APP_HOST))
+            .json(&json!({})),
+        bot.get(format!("{}/me", common::APP_HOST)),
+        bot.get(format!("{}/me/tokens", common::APP_HOST)),
+    ] {
+        let response = response.bearer_auth(&token).send().unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    let response = bot
+        .delete(format!(
+            "{}/rustaceans/{}",
+            common::APP_HOST,
+            rustacean["id"]
+        ))
+        .bearer_auth(&token)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = client
+        .get(format!("{}/me/tokens", common::APP_HOST))
+        .send()
+        .unwrap();
+    let tokens: Value = response.json().unwrap();
+    let listed = tokens
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|t| t["id"] == created["id"])
+        .unwrap();
+    assert!(listed["last_used_at"].is_string());
+
+    let response = client
+        .delete(format!("{}/me/tokens/{}", common::APP_HOST, created["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = bot
+        .get(format!("{}/crates", common::APP_HOST))
+        .bearer_auth(&token)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .delete(format!("{}/me/tokens/{}", common::APP_HOST, created["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    for body in [
+        json!({ "name": "ci", "scopes": ["crates:admin"], "expires_at": "2999-01-01T00:00:00" }),
+        json!({ "name": "ci", "scopes": [], "expires_at": "2999-01-01T00:00:00" }),
+        json!({ "name": "ci", "scopes": ["crates:read"], "expires_at": "2000-01-01T00:00:00" }),
+    ] {
+        let response = client
+            .post(format!("{}/me/tokens", common::APP_HOST))
+            .json(&body)
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}
@@ -1,4 +1,8 @@
-use std::process::Command;
+use std::{
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use reqwest::{
     blocking::{Client, ClientBuilder},
@@ -6,7 +10,22 @@ use reqwest::{
 };
 use serde_json::{json, Value};
 
-pub static APP_HOST: &'static str = "http://127.0.0.1:8000";
+pub static APP_HOST: &str = "http://127.0.0.1:8000";
+
+// Crate codes and emails are unique, so fixtures need distinct values.
+pub fn unique_suffix() -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .subsec_nanos();
+    format!(
+        "{}-{}-{}",
+        std::process::id(),
+        nanos,
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
 
 pub fn delete_test_rustacean(client: &Client, rustacean: Value) {
     let response = client
@@ -21,7 +40,7 @@ pub fn create_test_rustacean(client: &Client) -> Value {
         .post(format!("{}/rustaceans", APP_HOST))
         .json(&json!({
             "name": "Foo bar",
-            "email": "foo@bar.com"
+            "email": format!("foo-{}@bar.com", unique_suffix())
         }))
         .send()
         .unwrap();
@@ -44,9 +63,9 @@ pub fn create_test_crate(client: &Client, rustacean: &Value) -> Value {
         .post(format!("{}/crates", APP_HOST))
         .json(&json!({
             "rustacean_id": rustacean["id"],
-            "code": "code",
+            "code": format!("code-{}", unique_suffix()),
             "name": "name_values",
-            "version": "0.1",
+            "version": "0.1.0",
             "description": "some description",
         }))
         .send()
@@ -55,6 +74,24 @@ pub fn create_test_crate(client: &Client, rustacean: &Value) -> Value {
     response.json().unwrap()
 }
 
+// Versions from before SemVer was enforced can no longer be sent through the
+// API, so they are written straight to the database.
+pub fn set_legacy_crate_version(u_crate: &Value, version: &str) {
+    use diesel::sql_types::{Integer, Text};
+    use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+
+    let database_url = std::env::var("DATABASE_URL").expect("Cannot load DB url from env");
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        let mut c = AsyncPgConnection::establish(&database_url).await.unwrap();
+        diesel::sql_query("UPDATE crates SET version = $1 WHERE id = $2")
+            .bind::<Text, _>(version)
+            .bind::<Integer, _>(u_crate["id"].as_i64().unwrap() as i32)
+            .execute(&mut c)
+            .await
+            .unwrap();
+    });
+}
+
 pub fn get_logged_in_client(username: &str, role: &str) -> Client {
     let _ = Command::new("cargo")
         .arg("run")
@@ -101,6 +138,10 @@ pub fn get_client_with_logged_in_admin() -> Client {
     get_logged_in_client("test_admin", "admin")
 }
 
+pub fn get_client_with_logged_in_editor() -> Client {
+    get_logged_in_client("test_editor", "editor")
+}
+
 pub fn get_client_with_logged_in_viewer() -> Client {
     get_logged_in_client("test_viewer", "viewer")
 }
@@ -1,5 +1,6 @@
 use reqwest::StatusCode;
 use serde_json::{json, Value};
+use std::process::Command;
 
 pub mod common;
 
@@ -16,7 +17,7 @@ fn test_create_crate() {
             "rustacean_id": rustacean["id"],
             "code": "code",
             "name": "name_values",
-            "version": "0.1",
+            "version": "0.1.0",
             "description": "some description",
         }))
         .send()
@@ -33,9 +34,10 @@ fn test_create_crate() {
             "rustacean_id": rustacean["id"],
             "code": "code",
             "name": "name_values",
-            "version": "0.1",
+            "version": "0.1.0",
             "description": "some description",
-            "created_at": u_crate["created_at"]
+            "created_at": u_crate["created_at"],
+            "updated_at": u_crate["updated_at"]
         })
     );
 
@@ -44,6 +46,156 @@ fn test_create_crate() {
     common::delete_test_rustacean(&client, rustacean);
 }
 
+#[test]
+fn test_create_crate_duplicate_code() {
+    //Setup
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+    let b_crate = common::create_test_crate(&client, &rustacean);
+    // Differs only by case and '-' versus '_'.
+    let code = a_crate["code"]
+        .as_str()
+        .unwrap()
+        .to_uppercase()
+        .replace('-', "_");
+
+    //Test
+    let response = client
+        .post(format!("{}/crates", common::APP_HOST))
+        .json(&json!({
+            "rustacean_id": rustacean["id"],
+            "code": code,
+            "name": "name_values",
+            "version": "0.1.0",
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["code"], "already_exists");
+    assert_eq!(json["existing_id"], a_crate["id"]);
+
+    let response = client
+        .put(format!("{}/crates/{}", common::APP_HOST, b_crate["id"]))
+        .json(&json!({
+            "rustacean_id": rustacean["id"],
+            "code": code,
+            "name": "name_values",
+            "version": "0.1.0",
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["existing_id"], a_crate["id"]);
+
+    //Cleanup
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_crate(&client, b_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_create_crate_invalid_body() {
+    //Setup
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+
+    //Test
+    let response = client
+        .post(format!("{}/crates", common::APP_HOST))
+        .json(&json!({
+            "rustacean_id": rustacean["id"],
+            "name": "name_values",
+            "version": "0.1.0",
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        response.headers()["content-type"],
+        "application/problem+json"
+    );
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["code"], "validation_failed");
+    assert_eq!(json["errors"][0]["field"], "code");
+    assert_eq!(json["errors"][0]["message"], "missing field `code`");
+
+    let response = client
+        .post(format!("{}/crates", common::APP_HOST))
+        .json(&json!({
+            "rustacean_id": "not a number",
+            "code": "code",
+            "name": "name_values",
+            "version": "0.1.0",
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["errors"][0]["field"], "rustacean_id");
+
+    let response = client
+        .post(format!("{}/crates", common::APP_HOST))
+        .header("Content-Type", "application/json")
+        .body("{\"code\": ")
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["code"], "bad_request");
+
+    //Cleanup
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_create_crate_validation() {
+    //Setup
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+
+    //Test
+    let response = client
+        .post(format!("{}/crates", common::APP_HOST))
+        .json(&json!({
+            "rustacean_id": rustacean["id"],
+            "code": "x".repeat(65),
+            "name": " ",
+            "version": "latest",
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let json: Value = response.json().unwrap();
+    let fields: Vec<&str> = json["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, vec!["code", "name", "version"]);
+
+    let response = client
+        .post(format!("{}/crates", common::APP_HOST))
+        .json(&json!({
+            "rustacean_id": rustacean["id"],
+            "code": "1st crate!",
+            "name": "name_values",
+            "version": "0.1.0",
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["errors"].as_array().unwrap().len(), 1);
+    assert_eq!(json["errors"][0]["field"], "code");
+
+    //Cleanup
+    common::delete_test_rustacean(&client, rustacean);
+}
+
 #[test]
 fn test_get_crate() {
     // Setup
@@ -66,11 +218,12 @@ fn test_get_crate() {
         json!({
             "id": u_crate["id"],
             "rustacean_id": rustacean["id"],
-            "code": "code",
+            "code": u_crate["code"],
             "name": "name_values",
-            "version": "0.1",
+            "version": "0.1.0",
             "description": "some description",
-            "created_at": u_crate["created_at"]
+            "created_at": u_crate["created_at"],
+            "updated_at": u_crate["updated_at"]
         })
     );
     //Cleanup
@@ -78,6 +231,99 @@ fn test_get_crate() {
     common::delete_test_rustacean(&client, rustacean);
 }
 
+#[test]
+fn test_crate_by_code() {
+    //Setup
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let u_crate = common::create_test_crate(&client, &rustacean);
+    let code = u_crate["code"]
+        .as_str()
+        .unwrap()
+        .to_uppercase()
+        .replace('-', "_");
+
+    //Test
+    let response = client
+        .get(format!("{}/crates/by-code/{}", common::APP_HOST, code))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["id"], u_crate["id"]);
+
+    let response = client
+        .put(format!("{}/crates/by-code/{}", common::APP_HOST, code))
+        .json(&json!({
+            "rustacean_id": rustacean["id"],
+            "code": u_crate["code"],
+            "name": "renamed",
+            "version": "0.1.0",
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["id"], u_crate["id"]);
+    assert_eq!(json["name"], "renamed");
+
+    let response = client
+        .delete(format!("{}/crates/by-code/{}", common::APP_HOST, code))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = client
+        .get(format!("{}/crates/by-code/{}", common::APP_HOST, code))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    //Cleanup
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_get_crate_not_found() {
+    let client = common::get_client_with_logged_in_admin();
+
+    let response = client
+        .get(format!("{}/crates/{}", common::APP_HOST, -1))
+        .header("X-Request-Id", "trace-123")
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        response.headers()["content-type"],
+        "application/problem+json"
+    );
+    assert_eq!(response.headers()["x-request-id"], "trace-123");
+    let json: Value = response.json().unwrap();
+    assert_eq!(
+        json,
+        json!({
+            "type": "about:blank",
+            "title": "Not Found",
+            "status": 404,
+            "detail": "Not found",
+            "code": "not_found",
+            "request_id": "trace-123"
+        })
+    );
+
+    let response = client
+        .get(format!("{}/crates/{}", common::APP_HOST, -1))
+        .send()
+        .unwrap();
+    let request_id = response.headers()["x-request-id"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["request_id"], request_id);
+}
+
 #[test]
 fn test_update_crate() {
     let client = common::get_client_with_logged_in_admin();
@@ -91,7 +337,7 @@ fn test_update_crate() {
             "rustacean_id": rustacean["id"],
             "code": "code1",
             "name": "name_values1",
-            "version": "0.2",
+            "version": "0.2.0",
             "description": "some description1",
         }))
         .send()
@@ -108,9 +354,10 @@ fn test_update_crate() {
             "rustacean_id": rustacean["id"],
             "code": "code1",
             "name": "name_values1",
-            "version": "0.2",
+            "version": "0.2.0",
             "description": "some description1",
-            "created_at": u_crate["created_at"]
+            "created_at": u_crate["created_at"],
+            "updated_at": u_crate["updated_at"]
         })
     );
     //Change user test and text
@@ -121,7 +368,7 @@ fn test_update_crate() {
             "rustacean_id": rustacean2["id"],
             "code": "code1",
             "name": "name_values1",
-            "version": "0.2",
+            "version": "0.2.0",
             "description": test_text,
         }))
         .send()
@@ -136,53 +383,1248 @@ fn test_update_crate() {
             "rustacean_id": rustacean2["id"],
             "code": "code1",
             "name": "name_values1",
-            "version": "0.2",
+            "version": "0.2.0",
             "description": test_text,
-            "created_at": u_crate["created_at"]
+            "created_at": u_crate["created_at"],
+            "updated_at": u_crate["updated_at"]
         })
     );
 
+    let response = client
+        .patch(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .json(&json!({ "version": "0.1.0" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let json: Value = response.json().unwrap();
+    assert_eq!(
+        json["detail"],
+        "Version is lower than the latest release 0.2.0"
+    );
+
     common::delete_test_crate(&client, u_crate);
     common::delete_test_rustacean(&client, rustacean);
     common::delete_test_rustacean(&client, rustacean2);
 }
 
 #[test]
-fn test_delete_crate() {
+fn test_patch_crate() {
+    //Setup
     let client = common::get_client_with_logged_in_admin();
     let rustacean = common::create_test_rustacean(&client);
     let u_crate = common::create_test_crate(&client, &rustacean);
 
+    //Test
     let response = client
-        .delete(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .patch(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .header("Content-Type", "application/merge-patch+json")
+        .json(&json!({ "name": "patched", "description": null }))
         .send()
         .unwrap();
-    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    assert_eq!(
+        json,
+        json!({
+            "id": u_crate["id"],
+            "rustacean_id": rustacean["id"],
+            "code": u_crate["code"],
+            "name": "patched",
+            "version": "0.1.0",
+            "description": null,
+            "created_at": u_crate["created_at"],
+            "updated_at": json["updated_at"]
+        })
+    );
+
+    let response = client
+        .patch(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .json(&json!({ "name": null, "code": "not valid!" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["errors"][0]["field"], "name");
+
+    let response = client
+        .patch(format!("{}/crates/{}", common::APP_HOST, -1))
+        .json(&json!({ "name": "patched" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
 
+    //Cleanup
+    common::delete_test_crate(&client, u_crate);
     common::delete_test_rustacean(&client, rustacean);
 }
 
 #[test]
-fn test_get_crates() {
-    //SETUP
+fn test_patch_crate_concurrent() {
+    //Setup
     let client = common::get_client_with_logged_in_admin();
     let rustacean = common::create_test_rustacean(&client);
     let u_crate = common::create_test_crate(&client, &rustacean);
-    let b_crate = common::create_test_crate(&client, &rustacean);
+    let url = format!("{}/crates/{}", common::APP_HOST, u_crate["id"]);
 
-    //TEST
-    let respose = client
-        .get(format!("{}/crates", common::APP_HOST))
+    //Test
+    let response = client.get(&url).send().unwrap();
+    let etag = response.headers()["ETag"].to_str().unwrap().to_owned();
+
+    // Patches of different fields must not put each other's field back.
+    std::thread::scope(|s| {
+        for field in ["name", "description"] {
+            let (client, url) = (&client, &url);
+            s.spawn(move || {
+                for i in 0..10 {
+                    let response = client
+                        .patch(url)
+                        .json(&json!({ field: format!("{}-{}", field, i) }))
+                        .send()
+                        .unwrap();
+                    assert_eq!(response.status(), StatusCode::OK);
+                }
+            });
+        }
+    });
+
+    let json: Value = client.get(&url).send().unwrap().json().unwrap();
+    assert_eq!(json["name"], "name-9");
+    assert_eq!(json["description"], "description-9");
+
+    let response = client
+        .patch(&url)
+        .header("If-Match", &etag)
+        .json(&json!({ "name": "stale" }))
         .send()
         .unwrap();
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
 
-    assert_eq!(respose.status(), StatusCode::OK);
-    let json: Value = respose.json().unwrap();
-    assert!(json.as_array().unwrap().contains(&u_crate));
-    assert!(json.as_array().unwrap().contains(&b_crate));
+    //Cleanup
+    common::delete_test_crate(&client, u_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
 
-    //CLEANUP
+#[test]
+fn test_patch_crate_legacy_version() {
+    //Setup
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let u_crate = common::create_test_crate(&client, &rustacean);
+    common::set_legacy_crate_version(&u_crate, "0.1");
+
+    //Test
+    let response = client
+        .patch(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .json(&json!({ "description": "patched" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["version"], "0.1");
+    assert_eq!(json["description"], "patched");
+
+    let response = client
+        .patch(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .json(&json!({ "version": "0.2" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["errors"][0]["field"], "version");
+
+    //Cleanup
     common::delete_test_crate(&client, u_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_update_crate_if_match() {
+    //Setup
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let u_crate = common::create_test_crate(&client, &rustacean);
+
+    //Test
+    let response = client
+        .get(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response.headers()["ETag"].to_str().unwrap().to_owned();
+
+    let response = client
+        .patch(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .header("If-Match", &etag)
+        .json(&json!({ "name": "first" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let new_etag = response.headers()["ETag"].to_str().unwrap().to_owned();
+    assert_ne!(new_etag, etag);
+
+    // A second writer still holding the old version is turned away.
+    let response = client
+        .put(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .header("If-Match", &etag)
+        .json(&json!({
+            "rustacean_id": rustacean["id"],
+            "code": u_crate["code"],
+            "name": "second",
+            "version": "0.1.0",
+            "description": null,
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["code"], "precondition_failed");
+
+    let response = client
+        .delete(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .header("If-Match", &etag)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+    let response = client
+        .get(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .send()
+        .unwrap();
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["name"], "first");
+
+    let response = client
+        .delete(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .header("If-Match", &new_etag)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    //Cleanup
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_get_crates_conditional() {
+    //Setup
+    let client = common::get_client_with_logged_in_viewer();
+    let admin_client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&admin_client);
+    let u_crate = common::create_test_crate(&admin_client, &rustacean);
+    let b_crate = common::create_test_crate(&admin_client, &rustacean);
+
+    //Test
+    let response = client
+        .get(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Cache-Control"], "private, no-cache");
+    let etag = response.headers()["ETag"].to_str().unwrap().to_owned();
+    let last_modified = response.headers()["Last-Modified"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+
+    let response = client
+        .get(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .header("If-None-Match", &etag)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers()["ETag"], etag.as_str());
+    assert!(response.text().unwrap().is_empty());
+
+    let response = client
+        .get(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .header("If-Modified-Since", &last_modified)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    let response = client
+        .get(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .header("If-None-Match", "\"0\"")
+        .header("If-Modified-Since", &last_modified)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let list_url = format!(
+        "{}/crates?rustacean_id={}",
+        common::APP_HOST,
+        rustacean["id"]
+    );
+    let response = client.get(&list_url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().contains_key("Last-Modified"));
+    let list_etag = response.headers()["ETag"].to_str().unwrap().to_owned();
+
+    let response = client
+        .get(&list_url)
+        .header("If-None-Match", &list_etag)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    let response = admin_client
+        .patch(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .json(&json!({ "name": "renamed" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client
+        .get(&list_url)
+        .header("If-None-Match", &list_etag)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_ne!(response.headers()["ETag"], list_etag.as_str());
+    let list_etag = response.headers()["ETag"].to_str().unwrap().to_owned();
+    let list_last_modified = response.headers()["Last-Modified"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+
+    // Last-Modified only has a resolution of seconds.
+    std::thread::sleep(std::time::Duration::from_secs(1));
+    common::delete_test_crate(&admin_client, b_crate);
+
+    let response = client
+        .get(&list_url)
+        .header("If-None-Match", &list_etag)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_ne!(response.headers()["ETag"], list_etag.as_str());
+
+    let response = client
+        .get(&list_url)
+        .header("If-Modified-Since", &list_last_modified)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_ne!(
+        response.headers()["Last-Modified"],
+        list_last_modified.as_str()
+    );
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["items"].as_array().unwrap().len(), 1);
+
+    //Cleanup
+    common::delete_test_crate(&admin_client, u_crate);
+    common::delete_test_rustacean(&admin_client, rustacean);
+}
+
+#[test]
+fn test_crate_history() {
+    //Setup
+    let client = common::get_client_with_logged_in_admin();
+    let editor_client = common::get_client_with_logged_in_editor();
+    let rustacean = common::create_test_rustacean(&client);
+    let u_crate = common::create_test_crate(&client, &rustacean);
+
+    //Test
+    let response = editor_client
+        .patch(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .json(&json!({ "description": "changed" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client
+        .get(format!(
+            "{}/crates/{}/history",
+            common::APP_HOST,
+            u_crate["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let history: Value = response.json().unwrap();
+    let history = history.as_array().unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0]["action"], "update");
+    assert_eq!(history[0]["username"], "test_editor");
+    assert_eq!(history[0]["before"]["description"], "some description");
+    assert_eq!(history[0]["after"]["description"], "changed");
+    assert_eq!(history[1]["action"], "create");
+    assert_eq!(history[1]["username"], "test_admin");
+    assert_eq!(history[1]["before"], Value::Null);
+
+    let response = client
+        .get(format!(
+            "{}/crates/{}?as_of={}",
+            common::APP_HOST,
+            u_crate["id"],
+            history[1]["created_at"].as_str().unwrap()
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["description"], "some description");
+    assert!(json.get("deleted_at").is_none());
+
+    let response = client
+        .get(format!(
+            "{}/crates/{}?as_of=2000-01-01",
+            common::APP_HOST,
+            u_crate["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    common::delete_test_crate(&client, u_crate.clone());
+    let response = client
+        .get(format!(
+            "{}/crates/{}/history",
+            common::APP_HOST,
+            u_crate["id"]
+        ))
+        .send()
+        .unwrap();
+    let history: Value = response.json().unwrap();
+    assert_eq!(history[0]["action"], "delete");
+
+    //Cleanup
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_delete_crate() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let u_crate = common::create_test_crate(&client, &rustacean);
+
+    let response = client
+        .delete(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = client
+        .delete(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_restore_crate() {
+    //SETUP
+    let client = common::get_client_with_logged_in_admin();
+    let editor_client = common::get_client_with_logged_in_editor();
+    let rustacean = common::create_test_rustacean(&client);
+    let u_crate = common::create_test_crate(&client, &rustacean);
+
+    //TEST
+    let response = client
+        .delete(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = client
+        .get(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = editor_client
+        .post(format!(
+            "{}/crates/{}/restore",
+            common::APP_HOST,
+            u_crate["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // The deleted crate gives up its code.
+    let response = client
+        .post(format!("{}/crates", common::APP_HOST))
+        .json(&json!({
+            "rustacean_id": rustacean["id"],
+            "code": u_crate["code"],
+            "name": "taken over",
+            "version": "0.1.0",
+            "description": null,
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let b_crate: Value = response.json().unwrap();
+
+    let response = client
+        .post(format!(
+            "{}/crates/{}/versions/0.1.0/dependencies",
+            common::APP_HOST,
+            u_crate["id"]
+        ))
+        .json(&json!({ "dependency_id": b_crate["id"], "req": "^0.1" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = client
+        .post(format!(
+            "{}/crates/{}/versions",
+            common::APP_HOST,
+            u_crate["id"]
+        ))
+        .json(&json!({ "version": "0.2.0" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = client
+        .post(format!(
+            "{}/crates/{}/restore",
+            common::APP_HOST,
+            u_crate["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["existing_id"], b_crate["id"]);
+
+    common::delete_test_crate(&client, b_crate);
+    let response = client
+        .post(format!(
+            "{}/crates/{}/restore",
+            common::APP_HOST,
+            u_crate["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["name"], u_crate["name"]);
+
+    let response = client
+        .get(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client
+        .post(format!(
+            "{}/crates/{}/restore",
+            common::APP_HOST,
+            u_crate["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    //CLEANUP
+    common::delete_test_crate(&client, u_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_delete_crate_with_dependents() {
+    //SETUP
+    let client = common::get_client_with_logged_in_admin();
+    let editor_client = common::get_client_with_logged_in_editor();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+    let b_crate = common::create_test_crate(&client, &rustacean);
+    let response = client
+        .post(format!(
+            "{}/crates/{}/versions/0.1.0/dependencies",
+            common::APP_HOST,
+            a_crate["id"]
+        ))
+        .json(&json!({ "dependency_id": b_crate["id"], "req": "^0.1" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    //TEST
+    let response = client
+        .delete(format!("{}/crates/{}", common::APP_HOST, b_crate["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["crates"].as_array().unwrap().len(), 1);
+    assert_eq!(json["crates"][0]["id"], a_crate["id"]);
+
+    let response = editor_client
+        .delete(format!(
+            "{}/crates/{}?cascade=true",
+            common::APP_HOST,
+            b_crate["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = client
+        .delete(format!(
+            "{}/crates/{}?cascade=true",
+            common::APP_HOST,
+            b_crate["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = client
+        .get(format!(
+            "{}/crates/{}/dependencies",
+            common::APP_HOST,
+            a_crate["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json, json!([]));
+
+    //CLEANUP
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_get_crates() {
+    //SETUP
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let u_crate = common::create_test_crate(&client, &rustacean);
+    let b_crate = common::create_test_crate(&client, &rustacean);
+
+    //TEST
+    let respose = client
+        .get(format!("{}/crates", common::APP_HOST))
+        .send()
+        .unwrap();
+
+    assert_eq!(respose.status(), StatusCode::OK);
+    let json: Value = respose.json().unwrap();
+    assert!(json["items"].as_array().unwrap().contains(&u_crate));
+    assert!(json["items"].as_array().unwrap().contains(&b_crate));
+
+    //CLEANUP
+    common::delete_test_crate(&client, u_crate);
+    common::delete_test_crate(&client, b_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_get_crates_paginated() {
+    //SETUP
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+    let b_crate = common::create_test_crate(&client, &rustacean);
+    let c_crate = common::create_test_crate(&client, &rustacean);
+
+    //TEST
+    let respose = client
+        .get(format!(
+            "{}/crates?rustacean_id={}&sort=created_at&order=asc&per_page=2",
+            common::APP_HOST,
+            rustacean["id"]
+        ))
+        .send()
+        .unwrap();
+
+    assert_eq!(respose.status(), StatusCode::OK);
+    let json: Value = respose.json().unwrap();
+    assert_eq!(json["total"], 3);
+    assert_eq!(json["page"], 1);
+    assert_eq!(json["items"], json!([a_crate, b_crate]));
+    assert!(json["links"]["prev"].is_null());
+
+    let respose = client
+        .get(format!(
+            "{}{}",
+            common::APP_HOST,
+            json["links"]["next"].as_str().unwrap()
+        ))
+        .send()
+        .unwrap();
+
+    assert_eq!(respose.status(), StatusCode::OK);
+    let json: Value = respose.json().unwrap();
+    assert_eq!(json["page"], 2);
+    assert_eq!(json["items"], json!([c_crate]));
+    assert!(json["links"]["next"].is_null());
+
+    //CLEANUP
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_crate(&client, b_crate);
+    common::delete_test_crate(&client, c_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_get_crates_huge_page() {
+    let client = common::get_client_with_logged_in_admin();
+
+    let respose = client
+        .get(format!(
+            "{}/crates?page={}&per_page=100",
+            common::APP_HOST,
+            i64::MAX
+        ))
+        .send()
+        .unwrap();
+
+    assert_eq!(respose.status(), StatusCode::OK);
+    let json: Value = respose.json().unwrap();
+    assert_eq!(json["items"], json!([]));
+    assert!(json["links"]["next"].is_null());
+}
+
+#[test]
+fn test_get_crates_cursor() {
+    //SETUP
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+    let b_crate = common::create_test_crate(&client, &rustacean);
+    let c_crate = common::create_test_crate(&client, &rustacean);
+
+    //TEST
+    let respose = client
+        .get(format!(
+            "{}/crates?rustacean_id={}&order=asc&per_page=2&cursor=",
+            common::APP_HOST,
+            rustacean["id"]
+        ))
+        .send()
+        .unwrap();
+
+    assert_eq!(respose.status(), StatusCode::OK);
+    let json: Value = respose.json().unwrap();
+    assert_eq!(json["items"], json!([a_crate, b_crate]));
+    assert!(json["next_cursor"].is_string());
+
+    let respose = client
+        .get(format!(
+            "{}{}",
+            common::APP_HOST,
+            json["links"]["next"].as_str().unwrap()
+        ))
+        .send()
+        .unwrap();
+
+    assert_eq!(respose.status(), StatusCode::OK);
+    let json: Value = respose.json().unwrap();
+    assert_eq!(json["items"], json!([c_crate]));
+    assert!(json["next_cursor"].is_null());
+
+    let respose = client
+        .get(format!("{}/crates?cursor=not-a-cursor", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(respose.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    //CLEANUP
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_crate(&client, b_crate);
+    common::delete_test_crate(&client, c_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_search_crates() {
+    //SETUP
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let response = client
+        .post(format!("{}/crates", common::APP_HOST))
+        .json(&json!({
+            "rustacean_id": rustacean["id"],
+            "code": "airship",
+            "name": "Airship",
+            "version": "0.1.0",
+            "description": "Steering zeppelins through the clouds",
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let u_crate: Value = response.json().unwrap();
+
+    let response = client
+        .post(format!("{}/crates", common::APP_HOST))
+        .json(&json!({
+            "rustacean_id": rustacean["id"],
+            "code": "blimp",
+            "name": "Blimp",
+            "version": "0.1.0",
+            "description": "<img src=x onerror=alert(1)> dirigible",
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let x_crate: Value = response.json().unwrap();
+
+    //TEST
+    let response = client
+        .get(format!("{}/crates/search?q=dirigible", common::APP_HOST))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    let snippet = json["items"][0]["snippet"].as_str().unwrap();
+    assert!(snippet.contains("&lt;img src=x onerror=alert(1)&gt;"));
+    assert!(snippet.contains("<mark>dirigible</mark>"));
+
+    let response = client
+        .get(format!("{}/crates/search?q=zeppelin", common::APP_HOST))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    let hit = &json["items"][0];
+    assert_eq!(hit["id"], u_crate["id"]);
+    assert!(hit["rank"].as_f64().unwrap() > 0.0);
+    assert!(hit["snippet"]
+        .as_str()
+        .unwrap()
+        .contains("<mark>zeppelins</mark>"));
+
+    let response = client
+        .get(format!("{}/crates/search?q=", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    //CLEANUP
+    common::delete_test_crate(&client, x_crate);
+    common::delete_test_crate(&client, u_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_autocomplete_crates() {
+    //SETUP
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let response = client
+        .post(format!("{}/crates", common::APP_HOST))
+        .json(&json!({
+            "rustacean_id": rustacean["id"],
+            "code": "quartzite",
+            "name": "Quartzite",
+            "version": "0.1.0",
+            "description": null,
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let u_crate: Value = response.json().unwrap();
+
+    //TEST
+    for prefix in ["quar", "quartzit", "qaurtzite"] {
+        let response = client
+            .get(format!(
+                "{}/crates/autocomplete?prefix={}",
+                common::APP_HOST,
+                prefix
+            ))
+            .send()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let json: Value = response.json().unwrap();
+        assert_eq!(json[0]["id"], u_crate["id"], "prefix {}", prefix);
+    }
+
+    //CLEANUP
+    common::delete_test_crate(&client, u_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_crate_versions() {
+    //SETUP
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let u_crate = common::create_test_crate(&client, &rustacean);
+
+    //TEST
+    let response = client
+        .post(format!(
+            "{}/crates/{}/versions",
+            common::APP_HOST,
+            u_crate["id"]
+        ))
+        .json(&json!({
+            "version": "0.2.0",
+            "description": "second release",
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let version: Value = response.json().unwrap();
+    assert_eq!(version["crate_id"], u_crate["id"]);
+    assert_eq!(version["version"], "0.2.0");
+    assert_eq!(version["yanked"], false);
+
+    let response = client
+        .get(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .send()
+        .unwrap();
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["version"], "0.2.0");
+    assert_eq!(json["description"], "second release");
+
+    for version in ["0.10.0", "0.10.0-rc.1", "0.9.0"] {
+        let response = client
+            .post(format!(
+                "{}/crates/{}/versions",
+                common::APP_HOST,
+                u_crate["id"]
+            ))
+            .json(&json!({
+                "version": version,
+                "description": null,
+            }))
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let response = client
+        .get(format!(
+            "{}/crates/{}/versions",
+            common::APP_HOST,
+            u_crate["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    let versions: Vec<&str> = json
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["version"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        versions,
+        vec!["0.10.0", "0.10.0-rc.1", "0.9.0", "0.2.0", "0.1.0"]
+    );
+
+    let response = client
+        .get(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .send()
+        .unwrap();
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["version"], "0.10.0");
+
+    let response = client
+        .post(format!(
+            "{}/crates/{}/versions",
+            common::APP_HOST,
+            u_crate["id"]
+        ))
+        .json(&json!({
+            "version": "banana",
+            "description": null,
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let response = client
+        .post(format!(
+            "{}/crates/{}/versions",
+            common::APP_HOST,
+            u_crate["id"]
+        ))
+        .json(&json!({
+            "version": "0.2.0",
+            "description": null,
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    //CLEANUP
+    common::delete_test_crate(&client, u_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_crate_dependencies() {
+    //SETUP
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+    let b_crate = common::create_test_crate(&client, &rustacean);
+
+    //TEST
+    let response = client
+        .post(format!(
+            "{}/crates/{}/versions/0.1.0/dependencies",
+            common::APP_HOST,
+            a_crate["id"]
+        ))
+        .json(&json!({
+            "dependency_id": b_crate["id"],
+            "req": "^0.1",
+            "kind": "dev",
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let dependency: Value = response.json().unwrap();
+    assert_eq!(dependency["req"], "^0.1");
+    assert_eq!(dependency["kind"], "dev");
+    assert_eq!(dependency["optional"], false);
+
+    let response = client
+        .get(format!(
+            "{}/crates/{}/dependencies",
+            common::APP_HOST,
+            a_crate["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    assert_eq!(
+        json,
+        json!([{ "dependency": dependency, "crate": b_crate }])
+    );
+
+    let response = client
+        .get(format!(
+            "{}/crates/{}/dependents",
+            common::APP_HOST,
+            b_crate["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    assert_eq!(
+        json,
+        json!([{ "dependency": dependency, "crate": a_crate, "version": "0.1.0" }])
+    );
+
+    for (dependency_id, req) in [(&b_crate["id"], "banana"), (&a_crate["id"], "^0.1")] {
+        let response = client
+            .post(format!(
+                "{}/crates/{}/versions/0.1.0/dependencies",
+                common::APP_HOST,
+                a_crate["id"]
+            ))
+            .json(&json!({
+                "dependency_id": dependency_id,
+                "req": req,
+            }))
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    //CLEANUP
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_crate(&client, b_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_resolve_crate_version() {
+    //SETUP
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+    let b_crate = common::create_test_crate(&client, &rustacean);
+    let c_crate = common::create_test_crate(&client, &rustacean);
+    let publish = |u_crate: &Value, version: &str| {
+        let response = client
+            .post(format!(
+                "{}/crates/{}/versions",
+                common::APP_HOST,
+                u_crate["id"]
+            ))
+            .json(&json!({ "version": version, "description": null }))
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    };
+    let depend = |u_crate: &Value, version: &str, dependency: &Value, req: &str| {
+        let response = client
+            .post(format!(
+                "{}/crates/{}/versions/{}/dependencies",
+                common::APP_HOST,
+                u_crate["id"],
+                version
+            ))
+            .json(&json!({ "dependency_id": dependency["id"], "req": req }))
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    };
+    publish(&b_crate, "0.1.5");
+    publish(&b_crate, "0.2.0");
+    depend(&a_crate, "0.1.0", &b_crate, "^0.1");
+    depend(&a_crate, "0.1.0", &c_crate, "^2");
+    depend(&b_crate, "0.1.5", &a_crate, "^0.1");
+
+    //TEST
+    let response = client
+        .get(format!(
+            "{}/crates/{}/versions/0.1.0/resolve",
+            common::APP_HOST,
+            a_crate["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+
+    let tree = &json["tree"];
+    assert_eq!(tree["crate_id"], a_crate["id"]);
+    assert_eq!(tree["dependencies"].as_array().unwrap().len(), 1);
+    assert_eq!(tree["dependencies"][0]["crate_id"], b_crate["id"]);
+    assert_eq!(tree["dependencies"][0]["version"], "0.1.5");
+    assert_eq!(tree["dependencies"][0]["dependencies"][0]["cycle"], true);
+    let a_code = a_crate["code"].as_str().unwrap();
+    let b_code = b_crate["code"].as_str().unwrap();
+    assert_eq!(
+        json["cycles"],
+        json!([[
+            format!("{} 0.1.0", a_code),
+            format!("{} 0.1.5", b_code),
+            format!("{} 0.1.0", a_code)
+        ]])
+    );
+    assert_eq!(json["unsatisfiable"][0]["crate_id"], c_crate["id"]);
+    assert_eq!(json["unsatisfiable"][0]["req"], "^2");
+    assert_eq!(json["unsatisfiable"][0]["available"], json!(["0.1.0"]));
+    assert!(json["unsatisfiable"][0].get("deleted").is_none());
+
+    let response = client
+        .delete(format!(
+            "{}/crates/{}?cascade=true",
+            common::APP_HOST,
+            c_crate["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = client
+        .get(format!(
+            "{}/crates/{}/versions/0.1.0/resolve",
+            common::APP_HOST,
+            a_crate["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["unsatisfiable"][0]["crate_id"], c_crate["id"]);
+    assert_eq!(json["unsatisfiable"][0]["available"], json!([]));
+    assert_eq!(json["unsatisfiable"][0]["deleted"], true);
+
+    //CLEANUP
+    let response = client
+        .delete(format!(
+            "{}/rustaceans/{}?cascade=true",
+            common::APP_HOST,
+            rustacean["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+#[test]
+fn test_get_crates_graph() {
+    //SETUP
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+    let b_crate = common::create_test_crate(&client, &rustacean);
+    let c_crate = common::create_test_crate(&client, &rustacean);
+    for (from, to) in [(&a_crate, &b_crate), (&b_crate, &c_crate)] {
+        let response = client
+            .post(format!(
+                "{}/crates/{}/versions/0.1.0/dependencies",
+                common::APP_HOST,
+                from["id"]
+            ))
+            .json(&json!({ "dependency_id": to["id"], "req": "^0.1" }))
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    //TEST
+    let response = client
+        .get(format!(
+            "{}/crates/graph?root={}&depth=1",
+            common::APP_HOST,
+            a_crate["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    let nodes: Vec<&Value> = json["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|n| &n["id"])
+        .collect();
+    assert_eq!(nodes, vec![&a_crate["id"], &b_crate["id"]]);
+    assert_eq!(json["edges"][0]["from"], a_crate["id"]);
+    assert_eq!(json["edges"][0]["to"], b_crate["id"]);
+    assert_eq!(json["edges"].as_array().unwrap().len(), 1);
+
+    let response = client
+        .get(format!(
+            "{}/crates/graph?root={}&format=dot",
+            common::APP_HOST,
+            a_crate["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "text/vnd.graphviz");
+    let dot = response.text().unwrap();
+    assert!(dot.starts_with("digraph crates {"));
+    assert!(dot.contains(&format!("c{} -> c{}", a_crate["id"], b_crate["id"])));
+    assert!(dot.contains(&format!("c{} -> c{}", b_crate["id"], c_crate["id"])));
+
+    let output = Command::new("cargo")
+        .arg("run")
+        .arg("--bin")
+        .arg("cli")
+        .arg("crates")
+        .arg("graph")
+        .arg("--format")
+        .arg("json")
+        .arg("--root")
+        .arg(b_crate["id"].to_string())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["nodes"].as_array().unwrap().len(), 2);
+
+    //CLEANUP
+    common::delete_test_crate(&client, a_crate);
     common::delete_test_crate(&client, b_crate);
+    common::delete_test_crate(&client, c_crate);
     common::delete_test_rustacean(&client, rustacean);
 }
@@ -1,5 +1,6 @@
 use reqwest::StatusCode;
 use serde_json::{json, Value};
+use std::process::Command;
 
 pub mod common;
 #[test]
@@ -18,8 +19,8 @@ fn test_get_rustaceans() {
 
     assert_eq!(respose.status(), StatusCode::OK);
     let json: Value = respose.json().unwrap();
-    assert!(json.as_array().unwrap().contains(&rustacean1));
-    assert!(json.as_array().unwrap().contains(&rustacean2));
+    assert!(json["items"].as_array().unwrap().contains(&rustacean1));
+    assert!(json["items"].as_array().unwrap().contains(&rustacean2));
 
     //CLEANUP
     let client = common::get_client_with_logged_in_admin(); //return to admin to delete
@@ -49,8 +50,9 @@ fn test_get_rustacean() {
         json!({
             "id":rustacean["id"],
             "name": "Foo bar",
-            "email": "foo@bar.com",
+            "email": rustacean["email"],
             "created_at":rustacean["created_at"],
+            "updated_at":rustacean["updated_at"],
         })
     );
     let client = common::get_client_with_logged_in_admin(); //return to admin to delete
@@ -78,11 +80,57 @@ fn test_create_rustaceans() {
             "name": "Foo bar",
             "email": "foo@bar.com",
             "created_at":rustacean["created_at"],
+            "updated_at":rustacean["updated_at"],
         })
     );
     common::delete_test_rustacean(&client, rustacean);
 }
 
+#[test]
+fn test_create_rustacean_duplicate_email() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+
+    let response = client
+        .post(format!("{}/rustaceans", common::APP_HOST))
+        .json(&json!({
+            "name": "Foo bar",
+            "email": rustacean["email"].as_str().unwrap().to_uppercase(),
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["code"], "already_exists");
+    assert_eq!(json["existing_id"], rustacean["id"]);
+
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_create_rustacean_validation() {
+    let client = common::get_client_with_logged_in_admin();
+
+    let response = client
+        .post(format!("{}/rustaceans", common::APP_HOST))
+        .json(&json!({
+            "name": "",
+            "email": "not-an-email",
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["code"], "validation_failed");
+    assert_eq!(
+        json["errors"],
+        json!([
+            { "field": "email", "message": "must be a valid email address" },
+            { "field": "name", "message": "must not be blank" },
+        ])
+    );
+}
+
 #[test]
 fn test_update_rustacean() {
     let client = common::get_client_with_logged_in_admin();
@@ -108,11 +156,149 @@ fn test_update_rustacean() {
             "name": "Fuzz",
             "email": "fiiz@gmail.com",
             "created_at":rustacean["created_at"],
+            "updated_at":rustacean["updated_at"],
         })
     );
     common::delete_test_rustacean(&client, rustacean);
 }
 
+#[test]
+fn test_patch_rustacean() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+
+    let response = client
+        .patch(format!(
+            "{}/rustaceans/{}",
+            common::APP_HOST,
+            rustacean["id"]
+        ))
+        .header("Content-Type", "application/merge-patch+json")
+        .json(&json!({ "name": "Ferris" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["name"], "Ferris");
+    assert_eq!(json["email"], rustacean["email"]);
+
+    let response = client
+        .patch(format!(
+            "{}/rustaceans/{}",
+            common::APP_HOST,
+            rustacean["id"]
+        ))
+        .json(&json!({ "email": "nope" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_update_rustacean_if_match() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+
+    let response = client
+        .get(format!(
+            "{}/rustaceans/{}",
+            common::APP_HOST,
+            rustacean["id"]
+        ))
+        .send()
+        .unwrap();
+    let etag = response.headers()["ETag"].to_str().unwrap().to_owned();
+
+    let response = client
+        .put(format!(
+            "{}/rustaceans/{}",
+            common::APP_HOST,
+            rustacean["id"]
+        ))
+        .header("If-Match", &etag)
+        .json(&json!({ "name": "Ferris", "email": rustacean["email"] }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client
+        .patch(format!(
+            "{}/rustaceans/{}",
+            common::APP_HOST,
+            rustacean["id"]
+        ))
+        .header("If-Match", &etag)
+        .json(&json!({ "name": "Corro" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+    let response = client
+        .delete(format!(
+            "{}/rustaceans/{}",
+            common::APP_HOST,
+            rustacean["id"]
+        ))
+        .header("If-Match", "\"0\", *")
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+#[test]
+fn test_get_rustaceans_conditional() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+
+    let response = client
+        .get(format!(
+            "{}/rustaceans/{}",
+            common::APP_HOST,
+            rustacean["id"]
+        ))
+        .header("If-Modified-Since", "Thu, 01 Jan 1970 00:00:00 GMT")
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response.headers()["ETag"].to_str().unwrap().to_owned();
+
+    let response = client
+        .get(format!(
+            "{}/rustaceans/{}",
+            common::APP_HOST,
+            rustacean["id"]
+        ))
+        .header("If-None-Match", format!("W/{}", etag))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    // Other tests add rustaceans concurrently, so poll a listing that stays put.
+    let response = client
+        .get(format!(
+            "{}/rustaceans?created_after=2999-01-01",
+            common::APP_HOST
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.headers()["Cache-Control"], "private, no-cache");
+    let etag = response.headers()["ETag"].to_str().unwrap().to_owned();
+
+    let response = client
+        .get(format!(
+            "{}/rustaceans?created_after=2999-01-01",
+            common::APP_HOST
+        ))
+        .header("If-None-Match", &etag)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    common::delete_test_rustacean(&client, rustacean);
+}
+
 #[test]
 fn test_delete_rustacean() {
     let client = common::get_client_with_logged_in_admin();
@@ -127,4 +313,215 @@ fn test_delete_rustacean() {
         .send()
         .unwrap();
     assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = client
+        .delete(format!(
+            "{}/rustaceans/{}",
+            common::APP_HOST,
+            rustacean["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_delete_rustacean_with_crates() {
+    //SETUP
+    let client = common::get_client_with_logged_in_admin();
+    let editor_client = common::get_client_with_logged_in_editor();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+
+    //TEST
+    let response = client
+        .delete(format!(
+            "{}/rustaceans/{}",
+            common::APP_HOST,
+            rustacean["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["crates"][0]["id"], a_crate["id"]);
+    assert_eq!(json["crates"][0]["code"], a_crate["code"]);
+
+    let response = editor_client
+        .delete(format!(
+            "{}/rustaceans/{}?cascade=true",
+            common::APP_HOST,
+            rustacean["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = client
+        .delete(format!(
+            "{}/rustaceans/{}?cascade=true",
+            common::APP_HOST,
+            rustacean["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = client
+        .get(format!("{}/crates/{}", common::APP_HOST, a_crate["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_autocomplete_rustaceans() {
+    let client = common::get_client_with_logged_in_admin();
+    let response = client
+        .post(format!("{}/rustaceans", common::APP_HOST))
+        .json(&json!({
+            "name": "Ferris Crabbington",
+            "email": "ferris@crabbington.dev"
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let rustacean: Value = response.json().unwrap();
+
+    let client = common::get_client_with_logged_in_viewer(); //switch to viewer role
+    let response = client
+        .get(format!(
+            "{}/rustaceans/autocomplete?prefix=crabington&limit=5",
+            common::APP_HOST
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json[0]["id"], rustacean["id"]);
+    assert!(json[0]["score"].as_f64().unwrap() > 0.0);
+
+    let client = common::get_client_with_logged_in_admin(); //return to admin to delete
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_restore_and_purge_rustacean() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let u_crate = common::create_test_crate(&client, &rustacean);
+    let other = common::create_test_rustacean(&client);
+    let dependent = common::create_test_crate(&client, &other);
+    let response = client
+        .post(format!(
+            "{}/crates/{}/versions/0.1.0/dependencies",
+            common::APP_HOST,
+            dependent["id"]
+        ))
+        .json(&json!({ "dependency_id": u_crate["id"], "req": "^0.1" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = client
+        .delete(format!(
+            "{}/rustaceans/{}?cascade=true",
+            common::APP_HOST,
+            rustacean["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = client
+        .get(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = client
+        .post(format!(
+            "{}/crates/{}/restore",
+            common::APP_HOST,
+            u_crate["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let response = client
+        .post(format!(
+            "{}/rustaceans/{}/restore",
+            common::APP_HOST,
+            rustacean["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["email"], rustacean["email"]);
+
+    // Crates deleted along with the rustacean come back with it.
+    let response = client
+        .get(format!("{}/crates/{}", common::APP_HOST, u_crate["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client
+        .delete(format!(
+            "{}/rustaceans/{}?cascade=true",
+            common::APP_HOST,
+            rustacean["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let output = Command::new("cargo")
+        .arg("run")
+        .arg("--bin")
+        .arg("cli")
+        .arg("purge")
+        .arg("--older-than=-1")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+
+    let output = Command::new("cargo")
+        .arg("run")
+        .arg("--bin")
+        .arg("cli")
+        .arg("purge")
+        .arg("--older-than")
+        .arg("0")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let response = client
+        .post(format!(
+            "{}/rustaceans/{}/restore",
+            common::APP_HOST,
+            rustacean["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Dependency records on the purged crate go with it.
+    let response = client
+        .get(format!(
+            "{}/crates/{}/dependencies",
+            common::APP_HOST,
+            dependent["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json, json!([]));
+
+    common::delete_test_crate(&client, dependent);
+    common::delete_test_rustacean(&client, other);
 }
@@ -0,0 +1,11 @@
+use cr8s::semver;
+use std::cmp::Ordering;
+
+#[test]
+fn test_version_precedence() {
+    assert_eq!(semver::compare("1.0.0+a", "1.0.0+b"), Ordering::Equal);
+    assert_eq!(semver::compare("1.0.0-rc.1", "1.0.0"), Ordering::Less);
+    assert_eq!(semver::compare("0.1.0", "banana"), Ordering::Greater);
+    assert_eq!(semver::compare("0.1.0", "0.1"), Ordering::Greater);
+    assert_eq!(semver::compare("0.1", "0.2"), Ordering::Less);
+}